@@ -0,0 +1,74 @@
+//! Throughput of `get` at different pipeline depths over a loopback connection, with replies
+//! flushed once per batch and, as a baseline, after every request.
+//!
+//! Run with `cargo bench --bench pipeline`.
+
+use endpoint::{serve, ServerOptions};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use storage::HashMapStorage;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const REQUESTS: usize = 100_000;
+const DEPTHS: [usize; 4] = [1, 10, 100, 1000];
+
+async fn run(stream: &mut TcpStream, depth: usize) -> f64 {
+    let request = b"get key\r\n".repeat(depth);
+    let reply = b"VALUE key 0 5\r\nvalue\r\nEND\r\n";
+    let mut response = vec![0; reply.len() * depth];
+
+    let started = Instant::now();
+    for _ in 0..REQUESTS / depth {
+        stream.write_all(&request).await.expect("Can write");
+        stream
+            .read_exact(&mut response)
+            .await
+            .expect("Can read response");
+    }
+    assert_eq!(&response[response.len() - reply.len()..], reply);
+    (REQUESTS / depth * depth) as f64 / started.elapsed().as_secs_f64()
+}
+
+async fn listen(batch_replies: bool) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Can bind");
+    let address = listener.local_addr().expect("Has local address");
+    tokio::spawn(serve(
+        listener,
+        Arc::new(HashMapStorage::default()),
+        ServerOptions {
+            batch_replies,
+            ..ServerOptions::default()
+        },
+    ));
+    address
+}
+
+async fn connect(address: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(address).await.expect("Can connect");
+    stream.set_nodelay(true).expect("Can set nodelay");
+    stream
+        .write_all(b"set key 0 0 5\r\nvalue\r\n")
+        .await
+        .expect("Can write");
+    let mut stored = [0; 8];
+    stream.read_exact(&mut stored).await.expect("Can read");
+    stream
+}
+
+#[tokio::main]
+async fn main() {
+    let mut batched = connect(listen(true).await).await;
+    let mut unbatched = connect(listen(false).await).await;
+
+    for depth in DEPTHS {
+        let batched = run(&mut batched, depth).await;
+        let unbatched = run(&mut unbatched, depth).await;
+        println!(
+            "pipeline depth {depth:>4}: {batched:>12.0} gets/s batched, \
+             {unbatched:>12.0} gets/s flushing every reply ({:.2}x)",
+            batched / unbatched
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Throughput of a mixed get/set workload from concurrent tasks, with one global lock versus
+//! sharded locks.
+//!
+//! Run with `cargo bench --bench sharding`.
+
+use bytes::Bytes;
+use endpoint::{MemcachedHandler, SystemClock, WriteOptions};
+use std::sync::Arc;
+use std::time::Instant;
+use storage::{HashMapStorage, StorageOptions};
+
+const TASKS: usize = 16;
+const OPERATIONS_PER_TASK: usize = 100_000;
+const KEYS: u64 = 10_000;
+/// One in this many operations is a set; the rest are gets.
+const SET_EVERY: u64 = 5;
+const SHARDS: [usize; 3] = [1, 4, 16];
+
+async fn run(storage: Arc<HashMapStorage>) -> f64 {
+    let started = Instant::now();
+    let tasks: Vec<_> = (0..TASKS as u64)
+        .map(|task| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                // xorshift, so that every task walks its own sequence of keys.
+                let mut state = task * 0x9e37_79b9_7f4a_7c15 + 1;
+                for _ in 0..OPERATIONS_PER_TASK {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    let key = format!("key{}", state % KEYS);
+                    if state % SET_EVERY == 0 {
+                        let options = WriteOptions {
+                            flags: 0,
+                            expire: 0,
+                        };
+                        let _ = storage
+                            .set(key, Bytes::from_static(b"value"), options)
+                            .await;
+                    } else {
+                        let _ = storage.get(key).await;
+                    }
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.expect("Task completes");
+    }
+    (TASKS * OPERATIONS_PER_TASK) as f64 / started.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .build()
+        .expect("Can build runtime");
+    let threads = std::thread::available_parallelism().map_or(1, usize::from);
+    println!("{TASKS} tasks on {threads} threads, 1 set per {SET_EVERY} operations");
+
+    for shards in SHARDS {
+        let storage = Arc::new(
+            HashMapStorage::new(
+                StorageOptions {
+                    shards,
+                    ..StorageOptions::default()
+                },
+                Arc::new(SystemClock),
+            )
+            .unwrap(),
+        );
+        let throughput = runtime.block_on(run(storage));
+        println!("{shards:>3} shards: {throughput:>12.0} ops/s");
+    }
+}
@@ -0,0 +1,373 @@
+use super::is_valid_key;
+use crate::server::ServerOptions;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::debug;
+use tokio_util::codec::{Decoder, Encoder};
+
+pub(crate) const REQUEST_MAGIC: u8 = 0x80;
+const RESPONSE_MAGIC: u8 = 0x81;
+const HEADER_LENGTH: usize = 24;
+
+pub(crate) mod opcode {
+    pub(crate) const GET: u8 = 0x00;
+    pub(crate) const SET: u8 = 0x01;
+    pub(crate) const ADD: u8 = 0x02;
+    pub(crate) const REPLACE: u8 = 0x03;
+    pub(crate) const DELETE: u8 = 0x04;
+    pub(crate) const INCREMENT: u8 = 0x05;
+    pub(crate) const DECREMENT: u8 = 0x06;
+    pub(crate) const QUIT: u8 = 0x07;
+    pub(crate) const FLUSH: u8 = 0x08;
+    pub(crate) const GETQ: u8 = 0x09;
+    pub(crate) const NOOP: u8 = 0x0a;
+    pub(crate) const VERSION: u8 = 0x0b;
+    pub(crate) const GETK: u8 = 0x0c;
+    pub(crate) const GETKQ: u8 = 0x0d;
+    pub(crate) const APPEND: u8 = 0x0e;
+    pub(crate) const PREPEND: u8 = 0x0f;
+    pub(crate) const STAT: u8 = 0x10;
+    pub(crate) const SETQ: u8 = 0x11;
+    pub(crate) const ADDQ: u8 = 0x12;
+    pub(crate) const REPLACEQ: u8 = 0x13;
+    pub(crate) const DELETEQ: u8 = 0x14;
+    pub(crate) const INCREMENTQ: u8 = 0x15;
+    pub(crate) const DECREMENTQ: u8 = 0x16;
+    pub(crate) const QUITQ: u8 = 0x17;
+    pub(crate) const FLUSHQ: u8 = 0x18;
+    pub(crate) const APPENDQ: u8 = 0x19;
+    pub(crate) const PREPENDQ: u8 = 0x1a;
+    pub(crate) const TOUCH: u8 = 0x1c;
+    pub(crate) const GAT: u8 = 0x1d;
+    pub(crate) const GATQ: u8 = 0x1e;
+    pub(crate) const GATK: u8 = 0x23;
+    pub(crate) const GATKQ: u8 = 0x24;
+}
+
+pub(crate) mod status {
+    pub(crate) const NO_ERROR: u16 = 0x0000;
+    pub(crate) const KEY_NOT_FOUND: u16 = 0x0001;
+    pub(crate) const KEY_EXISTS: u16 = 0x0002;
+    pub(crate) const VALUE_TOO_LARGE: u16 = 0x0003;
+    pub(crate) const INVALID_ARGUMENTS: u16 = 0x0004;
+    pub(crate) const ITEM_NOT_STORED: u16 = 0x0005;
+    pub(crate) const NON_NUMERIC_VALUE: u16 = 0x0006;
+    pub(crate) const UNKNOWN_COMMAND: u16 = 0x0081;
+    pub(crate) const INTERNAL_ERROR: u16 = 0x0084;
+}
+
+#[derive(Debug)]
+pub(crate) struct BinaryRequest {
+    pub(crate) opcode: u8,
+    pub(crate) opaque: u32,
+    pub(crate) cas: u64,
+    pub(crate) extras: Bytes,
+    pub(crate) key: Bytes,
+    pub(crate) value: Bytes,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(crate) struct BinaryResponse {
+    pub(crate) opcode: u8,
+    pub(crate) status: u16,
+    pub(crate) opaque: u32,
+    pub(crate) cas: u64,
+    pub(crate) extras: Bytes,
+    pub(crate) key: Bytes,
+    pub(crate) value: Bytes,
+}
+
+impl BinaryResponse {
+    /// Empty reply that echoes the request's opcode and opaque.
+    pub(crate) fn to(request: &BinaryRequest) -> Self {
+        Self {
+            opcode: request.opcode,
+            status: status::NO_ERROR,
+            opaque: request.opaque,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn error(mut self, status: u16, message: &str) -> Self {
+        self.status = status;
+        self.value = Bytes::copy_from_slice(message.as_bytes());
+        self
+    }
+}
+
+/// Frames binary protocol packets; the body is interpreted by the dispatcher.
+#[derive(Debug)]
+pub(crate) struct BinaryCodec {
+    max_item_size: usize,
+    max_key_length: usize,
+    swallow: usize,
+}
+
+impl BinaryCodec {
+    pub(crate) fn new(options: &ServerOptions) -> Self {
+        Self {
+            max_item_size: options.max_item_size,
+            max_key_length: options.max_key_length,
+            swallow: 0,
+        }
+    }
+}
+
+impl Decoder for BinaryCodec {
+    type Item = Result<BinaryRequest, BinaryResponse>;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if self.swallow > 0 {
+            let count = self.swallow.min(src.len());
+            src.advance(count);
+            self.swallow -= count;
+            if self.swallow > 0 {
+                return Ok(None);
+            }
+        }
+        if src.len() < HEADER_LENGTH {
+            return Ok(None);
+        }
+
+        let mut header = &src[..HEADER_LENGTH];
+        let magic = header.get_u8();
+        let opcode = header.get_u8();
+        let key_length = header.get_u16() as usize;
+        let extras_length = header.get_u8() as usize;
+        let _data_type = header.get_u8();
+        let _vbucket = header.get_u16();
+        let body_length = header.get_u32() as usize;
+        let opaque = header.get_u32();
+        let cas = header.get_u64();
+
+        if magic != REQUEST_MAGIC || key_length + extras_length > body_length {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "bad binary packet header",
+            ));
+        }
+        if body_length - key_length - extras_length > self.max_item_size {
+            debug!("binary body too large: {body_length}");
+            src.advance(HEADER_LENGTH);
+            let count = body_length.min(src.len());
+            src.advance(count);
+            self.swallow = body_length - count;
+            let response = BinaryResponse {
+                opcode,
+                opaque,
+                ..BinaryResponse::default()
+            };
+            return Ok(Some(Err(
+                response.error(status::VALUE_TOO_LARGE, "Too large.")
+            )));
+        }
+        if src.len() < HEADER_LENGTH + body_length {
+            src.reserve(HEADER_LENGTH + body_length - src.len());
+            return Ok(None);
+        }
+
+        src.advance(HEADER_LENGTH);
+        let mut body = src.split_to(body_length).freeze();
+        let extras = body.split_to(extras_length);
+        let key = body.split_to(key_length);
+        if !key.is_empty() && !is_valid_key(&key, self.max_key_length) {
+            let response = BinaryResponse {
+                opcode,
+                opaque,
+                ..BinaryResponse::default()
+            };
+            return Ok(Some(Err(
+                response.error(status::INVALID_ARGUMENTS, "Invalid arguments")
+            )));
+        }
+        Ok(Some(Ok(BinaryRequest {
+            opcode,
+            opaque,
+            cas,
+            extras,
+            key,
+            value: body,
+        })))
+    }
+}
+
+impl Encoder<BinaryResponse> for BinaryCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: BinaryResponse, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let body_length = item.extras.len() + item.key.len() + item.value.len();
+        dst.reserve(HEADER_LENGTH + body_length);
+        dst.put_u8(RESPONSE_MAGIC);
+        dst.put_u8(item.opcode);
+        dst.put_u16(item.key.len() as u16);
+        dst.put_u8(item.extras.len() as u8);
+        dst.put_u8(0);
+        dst.put_u16(item.status);
+        dst.put_u32(body_length as u32);
+        dst.put_u32(item.opaque);
+        dst.put_u64(item.cas);
+        dst.put_slice(&item.extras);
+        dst.put_slice(&item.key);
+        dst.put_slice(&item.value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(opcode: u8, extras: &[u8], key: &[u8], value: &[u8]) -> BytesMut {
+        let mut src = BytesMut::new();
+        src.put_u8(REQUEST_MAGIC);
+        src.put_u8(opcode);
+        src.put_u16(key.len() as u16);
+        src.put_u8(extras.len() as u8);
+        src.put_u8(0);
+        src.put_u16(0);
+        src.put_u32((extras.len() + key.len() + value.len()) as u32);
+        src.put_u32(0xdeadbeef);
+        src.put_u64(42);
+        src.put_slice(extras);
+        src.put_slice(key);
+        src.put_slice(value);
+        src
+    }
+
+    #[test]
+    fn test_decode_request() {
+        let mut codec = BinaryCodec::new(&ServerOptions::default());
+        let mut src = packet(opcode::SET, &[0, 0, 0, 5, 0, 0, 0, 0], b"key", b"value");
+
+        let request = codec
+            .decode(&mut src)
+            .expect("Can decode")
+            .expect("Has packet")
+            .expect("Is request");
+        assert_eq!(request.opcode, opcode::SET);
+        assert_eq!(request.opaque, 0xdeadbeef);
+        assert_eq!(request.cas, 42);
+        assert_eq!(&request.extras[..], &[0, 0, 0, 5, 0, 0, 0, 0]);
+        assert_eq!(&request.key[..], b"key");
+        assert_eq!(&request.value[..], b"value");
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_decode_partial_packet() {
+        let mut codec = BinaryCodec::new(&ServerOptions::default());
+        let full = packet(opcode::GET, &[], b"key", b"");
+        let mut src = BytesMut::from(&full[..HEADER_LENGTH + 1]);
+
+        assert!(codec.decode(&mut src).expect("Can decode").is_none());
+        src.extend_from_slice(&full[HEADER_LENGTH + 1..]);
+        assert!(codec.decode(&mut src).expect("Can decode").is_some());
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_magic() {
+        let mut codec = BinaryCodec::new(&ServerOptions::default());
+        let mut src = packet(opcode::GET, &[], b"key", b"");
+        src[0] = 0x81;
+
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_decode_too_large_value_is_skipped() {
+        let mut codec = BinaryCodec::new(&ServerOptions {
+            max_item_size: 4,
+            ..ServerOptions::default()
+        });
+        let mut src = packet(opcode::SET, &[0; 8], b"key", b"value");
+        src.extend_from_slice(&packet(opcode::NOOP, &[], b"", b""));
+
+        let response = codec
+            .decode(&mut src)
+            .expect("Can decode")
+            .expect("Has packet")
+            .expect_err("Is rejected");
+        assert_eq!(response.status, status::VALUE_TOO_LARGE);
+        assert_eq!(response.opaque, 0xdeadbeef);
+        let request = codec
+            .decode(&mut src)
+            .expect("Can decode")
+            .expect("Has packet")
+            .expect("Is request");
+        assert_eq!(request.opcode, opcode::NOOP);
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_key() {
+        let mut codec = BinaryCodec::new(&ServerOptions {
+            max_key_length: 4,
+            ..ServerOptions::default()
+        });
+        let mut src = packet(opcode::GET, &[], b"toolong", b"");
+        src.extend_from_slice(&packet(opcode::GET, &[], b"k\x01", b""));
+        src.extend_from_slice(&packet(opcode::GET, &[], b"key", b""));
+
+        for _ in 0..2 {
+            let response = codec
+                .decode(&mut src)
+                .expect("Can decode")
+                .expect("Has packet")
+                .expect_err("Is rejected");
+            assert_eq!(response.status, status::INVALID_ARGUMENTS);
+        }
+        assert!(codec
+            .decode(&mut src)
+            .expect("Can decode")
+            .expect("Has packet")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let mut dst = BytesMut::new();
+        BinaryCodec::new(&ServerOptions::default())
+            .encode(
+                BinaryResponse {
+                    opcode: opcode::GETK,
+                    status: status::NO_ERROR,
+                    opaque: 7,
+                    cas: 9,
+                    extras: Bytes::from_static(&[0, 0, 0, 1]),
+                    key: Bytes::from("key"),
+                    value: Bytes::from("value"),
+                },
+                &mut dst,
+            )
+            .expect("Can encode");
+
+        let mut expected = vec![
+            0x81,
+            opcode::GETK,
+            0,
+            3,
+            4,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            12,
+            0,
+            0,
+            0,
+            7,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            9,
+        ];
+        expected.extend_from_slice(&[0, 0, 0, 1]);
+        expected.extend_from_slice(b"keyvalue");
+        assert_eq!(&dst[..], &expected[..]);
+    }
+}
@@ -0,0 +1,416 @@
+use super::{MemcachedError, MemcachedResponse, MetaResponse, MetaStatus};
+use crate::handler::{
+    MemcachedResult, MetaArithmeticMode, MetaArithmeticOptions, MetaDeleteOptions, MetaSetMode,
+    MetaSetOptions, WriteOptions,
+};
+use std::str::FromStr;
+
+const MAX_OPAQUE_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum MetaCommand {
+    Get,
+    Set,
+    Delete,
+    Arithmetic,
+}
+
+impl MetaCommand {
+    fn allowed_flags(&self) -> &'static str {
+        match self {
+            MetaCommand::Get => "cfklOqstTv",
+            MetaCommand::Set => "cCFIkMOqT",
+            MetaCommand::Delete => "CIkOqT",
+            MetaCommand::Arithmetic => "cCDJkMNOqtv",
+        }
+    }
+
+    fn miss(&self) -> MetaStatus {
+        match self {
+            MetaCommand::Get => MetaStatus::Miss,
+            _ => MetaStatus::NotFound,
+        }
+    }
+
+    /// Statuses left out of the reply stream in quiet (`q`) mode.
+    fn hides(&self, status: MetaStatus) -> bool {
+        match self {
+            MetaCommand::Get => status == MetaStatus::Miss,
+            MetaCommand::Set => status == MetaStatus::Success,
+            MetaCommand::Delete | MetaCommand::Arithmetic => {
+                matches!(status, MetaStatus::Success | MetaStatus::NotFound)
+            }
+        }
+    }
+}
+
+fn bad_token() -> MemcachedError {
+    MemcachedError::Client("bad token in command line format".to_string())
+}
+
+fn parse_meta_token<T: FromStr>(token: &str) -> Result<T, MemcachedError> {
+    T::from_str(token).map_err(|_| bad_token())
+}
+
+/// Flags of a meta command in request order, each with its (possibly empty) token.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct MetaFlags(Vec<(char, String)>);
+
+impl MetaFlags {
+    pub(crate) fn parse(command: MetaCommand, tokens: &[&str]) -> Result<Self, MemcachedError> {
+        let mut flags: Vec<(char, String)> = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let mut chars = token.chars();
+            let flag = chars
+                .next()
+                .filter(|flag| command.allowed_flags().contains(*flag))
+                .ok_or_else(|| MemcachedError::Client("invalid flag".to_string()))?;
+            if flags.iter().any(|(f, _)| *f == flag) {
+                return Err(MemcachedError::Client("duplicate flag".to_string()));
+            }
+            let token = chars.as_str();
+            if flag == 'O' && token.len() > MAX_OPAQUE_LENGTH {
+                return Err(MemcachedError::Client("opaque token too long".to_string()));
+            }
+            flags.push((flag, token.to_string()));
+        }
+        Ok(Self(flags))
+    }
+
+    pub(crate) fn has(&self, flag: char) -> bool {
+        self.0.iter().any(|(f, _)| *f == flag)
+    }
+
+    fn token(&self, flag: char) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(f, _)| *f == flag)
+            .map(|(_, token)| token.as_str())
+    }
+
+    fn numeric<T: FromStr>(&self, flag: char) -> Result<Option<T>, MemcachedError> {
+        self.token(flag).map(parse_meta_token).transpose()
+    }
+
+    pub(crate) fn touch(&self) -> Result<Option<i64>, MemcachedError> {
+        self.numeric('T')
+    }
+
+    pub(crate) fn set_options(&self) -> Result<(WriteOptions, MetaSetOptions), MemcachedError> {
+        let mode = match self.token('M') {
+            None => MetaSetMode::Set,
+            Some("E" | "e") => MetaSetMode::Add,
+            Some("A" | "a") => MetaSetMode::Append,
+            Some("P" | "p") => MetaSetMode::Prepend,
+            Some("R" | "r") => MetaSetMode::Replace,
+            Some("S" | "s") => MetaSetMode::Set,
+            Some(_) => return Err(bad_token()),
+        };
+        let options = WriteOptions {
+            flags: self.numeric('F')?.unwrap_or(0),
+            expire: self.numeric('T')?.unwrap_or(0),
+        };
+        let meta = MetaSetOptions {
+            mode,
+            compare_cas: self.numeric('C')?,
+            invalidate: self.has('I'),
+        };
+        Ok((options, meta))
+    }
+
+    pub(crate) fn delete_options(&self) -> Result<MetaDeleteOptions, MemcachedError> {
+        Ok(MetaDeleteOptions {
+            compare_cas: self.numeric('C')?,
+            invalidate: self.has('I'),
+            expire: self.numeric('T')?,
+        })
+    }
+
+    pub(crate) fn arithmetic_options(&self) -> Result<MetaArithmeticOptions, MemcachedError> {
+        let mode = match self.token('M') {
+            None | Some("I" | "i" | "+") => MetaArithmeticMode::Increment,
+            Some("D" | "d" | "-") => MetaArithmeticMode::Decrement,
+            Some(_) => return Err(bad_token()),
+        };
+        Ok(MetaArithmeticOptions {
+            mode,
+            delta: self.numeric('D')?.unwrap_or(1),
+            initial: self.numeric('J')?.unwrap_or(0),
+            autovivify: self.numeric('N')?,
+            compare_cas: self.numeric('C')?,
+        })
+    }
+
+    /// Turns a handler result into the meta reply, echoing the requested return flags.
+    pub(crate) fn reply(
+        &self,
+        command: MetaCommand,
+        key: &str,
+        res: MemcachedResult,
+    ) -> MemcachedResult {
+        let mut flags = Vec::new();
+        let mut value = None;
+        let status = match res {
+            Ok(MemcachedResponse::Item(item)) => {
+                for (flag, token) in &self.0 {
+                    match flag {
+                        'O' => flags.push(format!("O{token}")),
+                        'k' => flags.push(format!("k{key}")),
+                        'f' => flags.push(format!("f{}", item.value.flags)),
+                        't' => flags.push(match item.ttl {
+                            Some(ttl) => format!("t{ttl}"),
+                            None => "t-1".to_string(),
+                        }),
+                        'c' => flags.push(format!("c{}", item.value.cas.unwrap_or(0))),
+                        's' => flags.push(format!("s{}", item.value.value.len())),
+                        'l' => flags.push(format!("l{}", item.last_access)),
+                        _ => {}
+                    }
+                }
+                if command == MetaCommand::Get {
+                    if item.win {
+                        flags.push("W".to_string());
+                    }
+                    if item.stale {
+                        flags.push("X".to_string());
+                    }
+                    if item.claimed {
+                        flags.push("Z".to_string());
+                    }
+                }
+                if self.has('v') {
+                    value = Some(item.value.value);
+                    MetaStatus::Value
+                } else {
+                    MetaStatus::Success
+                }
+            }
+            res => {
+                let status = match res {
+                    Ok(_) => MetaStatus::Success,
+                    Err(MemcachedError::NotFound) => command.miss(),
+                    Err(MemcachedError::NotStored) => MetaStatus::NotStored,
+                    Err(MemcachedError::Exists) => MetaStatus::Exists,
+                    Err(e) => return Err(e),
+                };
+                for (flag, token) in &self.0 {
+                    match flag {
+                        'O' => flags.push(format!("O{token}")),
+                        'k' => flags.push(format!("k{key}")),
+                        _ => {}
+                    }
+                }
+                status
+            }
+        };
+        Ok(MemcachedResponse::Meta(MetaResponse {
+            status,
+            flags,
+            value,
+            quiet: self.has('q') && command.hides(status),
+        }))
+    }
+}
+
+/// Formats `me` output for an item, or `EN` on a miss.
+pub(crate) fn debug_reply(key: &str, res: MemcachedResult) -> MemcachedResult {
+    let (status, flags) = match res {
+        Ok(MemcachedResponse::Item(item)) => {
+            let exp = item
+                .ttl
+                .map(|ttl| ttl.to_string())
+                .unwrap_or_else(|| "-1".to_string());
+            let flags = vec![
+                key.to_string(),
+                format!("exp={exp}"),
+                format!("la={}", item.last_access),
+                format!("cas={}", item.value.cas.unwrap_or(0)),
+                format!("size={}", item.value.value.len()),
+            ];
+            (MetaStatus::Debug, flags)
+        }
+        Ok(_) | Err(MemcachedError::NotFound) => (MetaStatus::Miss, vec![]),
+        Err(e) => return Err(e),
+    };
+    Ok(MemcachedResponse::Meta(MetaResponse {
+        status,
+        flags,
+        value: None,
+        quiet: false,
+    }))
+}
+
+pub(crate) fn noop_reply() -> MemcachedResult {
+    Ok(MemcachedResponse::Meta(MetaResponse {
+        status: MetaStatus::Noop,
+        flags: vec![],
+        value: None,
+        quiet: false,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{MemcachedItem, MemcachedValue};
+    use bytes::Bytes;
+
+    fn item() -> MemcachedItem {
+        MemcachedItem {
+            value: MemcachedValue {
+                key: "key".to_string(),
+                flags: 5,
+                value: Bytes::from("value"),
+                cas: Some(7),
+            },
+            ttl: None,
+            last_access: 3,
+            stale: false,
+            win: false,
+            claimed: false,
+        }
+    }
+
+    fn flags(command: MetaCommand, tokens: &[&str]) -> MetaFlags {
+        MetaFlags::parse(command, tokens).expect("Valid flags")
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_flags() {
+        assert_eq!(
+            MetaFlags::parse(MetaCommand::Get, &["x"]),
+            Err(MemcachedError::Client("invalid flag".to_string()))
+        );
+        assert_eq!(
+            MetaFlags::parse(MetaCommand::Get, &["v", "v"]),
+            Err(MemcachedError::Client("duplicate flag".to_string()))
+        );
+        assert_eq!(
+            MetaFlags::parse(MetaCommand::Get, &["O0123456789abcdef0123456789abcdef0"]),
+            Err(MemcachedError::Client("opaque token too long".to_string()))
+        );
+        assert_eq!(
+            flags(MetaCommand::Get, &["Tsoon"]).touch(),
+            Err(bad_token())
+        );
+    }
+
+    #[test]
+    fn test_set_options() {
+        let (options, meta) = flags(MetaCommand::Set, &["F3", "T60", "C9", "I", "MA"])
+            .set_options()
+            .expect("Valid options");
+        assert_eq!((options.flags, options.expire), (3, 60));
+        assert_eq!(
+            meta,
+            MetaSetOptions {
+                mode: MetaSetMode::Append,
+                compare_cas: Some(9),
+                invalidate: true,
+            }
+        );
+        assert_eq!(
+            flags(MetaCommand::Set, &["MX"]).set_options().unwrap_err(),
+            bad_token()
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_options() {
+        assert_eq!(
+            flags(MetaCommand::Arithmetic, &[]).arithmetic_options(),
+            Ok(MetaArithmeticOptions::default())
+        );
+        assert_eq!(
+            flags(MetaCommand::Arithmetic, &["MD", "D5", "J10", "N0"]).arithmetic_options(),
+            Ok(MetaArithmeticOptions {
+                mode: MetaArithmeticMode::Decrement,
+                delta: 5,
+                initial: 10,
+                autovivify: Some(0),
+                compare_cas: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_reply_with_value_and_return_flags() {
+        let flags = flags(
+            MetaCommand::Get,
+            &["Oabc", "v", "c", "f", "t", "k", "s", "l"],
+        );
+        assert_eq!(
+            flags.reply(MetaCommand::Get, "key", Ok(MemcachedResponse::Item(item()))),
+            Ok(MemcachedResponse::Meta(MetaResponse {
+                status: MetaStatus::Value,
+                flags: ["Oabc", "c7", "f5", "t-1", "kkey", "s5", "l3"]
+                    .map(str::to_string)
+                    .to_vec(),
+                value: Some(Bytes::from("value")),
+                quiet: false,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_reply_marks_stale_items() {
+        let flags = flags(MetaCommand::Get, &[]);
+        let mut stale = item();
+        stale.stale = true;
+        stale.win = true;
+        assert_eq!(
+            flags.reply(MetaCommand::Get, "key", Ok(MemcachedResponse::Item(stale))),
+            Ok(MemcachedResponse::Meta(MetaResponse {
+                status: MetaStatus::Success,
+                flags: vec!["W".to_string(), "X".to_string()],
+                value: None,
+                quiet: false,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_reply_quiet_mode() {
+        let get = flags(MetaCommand::Get, &["q", "k"]);
+        assert_eq!(
+            get.reply(MetaCommand::Get, "key", Err(MemcachedError::NotFound)),
+            Ok(MemcachedResponse::Meta(MetaResponse {
+                status: MetaStatus::Miss,
+                flags: vec!["kkey".to_string()],
+                value: None,
+                quiet: true,
+            }))
+        );
+
+        let set = flags(MetaCommand::Set, &["q"]);
+        assert!(matches!(
+            set.reply(MetaCommand::Set, "key", Err(MemcachedError::NotStored)),
+            Ok(MemcachedResponse::Meta(MetaResponse {
+                status: MetaStatus::NotStored,
+                quiet: false,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            set.reply(MetaCommand::Set, "key", Ok(MemcachedResponse::Item(item()))),
+            Ok(MemcachedResponse::Meta(MetaResponse {
+                status: MetaStatus::Success,
+                quiet: true,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_reply_passes_through_client_errors() {
+        let flags = flags(MetaCommand::Arithmetic, &[]);
+        assert_eq!(
+            flags.reply(
+                MetaCommand::Arithmetic,
+                "key",
+                Err(MemcachedError::FailedToParseInteger)
+            ),
+            Err(MemcachedError::FailedToParseInteger)
+        );
+    }
+}
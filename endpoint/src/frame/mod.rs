@@ -1,134 +1,220 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use log::{debug, warn};
 use std::fmt::Write;
-use std::num::{IntErrorKind, ParseIntError};
 use std::str::FromStr;
-use std::time::Duration;
-use tokio_util::bytes::{Buf, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
 
+pub(crate) mod binary;
+mod meta;
 mod request;
 mod response;
 
-use crate::handler::WriteOptions;
+use crate::handler::{MetaSetOptions, WriteOptions};
+use crate::server::ServerOptions;
+pub(crate) use meta::*;
 pub(crate) use request::*;
 pub use response::*;
 
-trait OrElseResult {
-    type Error;
-    type Item;
+#[derive(Debug, Clone, Copy)]
+enum StorageCommand {
+    Set,
+    Add,
+    Replace,
+    Append,
+    Prepend,
+    Cas,
+    MetaSet,
+}
 
-    fn or_else_result<F: FnOnce() -> Result<Self::Item, Self::Error>>(
-        self,
-        f: F,
-    ) -> Result<Self::Item, Self::Error>;
+#[derive(Debug)]
+struct PendingData {
+    command: StorageCommand,
+    key: String,
+    options: WriteOptions,
+    number_of_bytes: usize,
+    cas_unique: u64,
+    noreply: bool,
+    meta: MetaSetOptions,
+    meta_flags: MetaFlags,
 }
 
-impl<T> OrElseResult for Option<T> {
-    type Error = std::io::Error;
-    type Item = Option<T>;
-
-    fn or_else_result<F: FnOnce() -> Result<Self::Item, Self::Error>>(
-        self,
-        f: F,
-    ) -> Result<Self::Item, Self::Error> {
-        match self {
-            None => f(),
-            Some(v) => Ok(Some(v)),
+impl StorageCommand {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "set" => Some(StorageCommand::Set),
+            "add" => Some(StorageCommand::Add),
+            "replace" => Some(StorageCommand::Replace),
+            "append" => Some(StorageCommand::Append),
+            "prepend" => Some(StorageCommand::Prepend),
+            "cas" => Some(StorageCommand::Cas),
+            _ => None,
         }
     }
 }
 
-trait MapToInteger {
-    fn map_to_integer<I: FromStr<Err = ParseIntError>>(
-        s: &str,
-    ) -> Result<Option<I>, std::io::Error> {
-        debug!("map_to_integer: {s}");
-        match I::from_str(s) {
-            Ok(v) => Ok(Some(v)),
-            Err(e) => {
-                if *e.kind() == IntErrorKind::InvalidDigit {
-                    Ok(None)
-                } else {
-                    Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
-                }
-            }
+impl PendingData {
+    fn into_request(self, value: Bytes) -> MemcachedRequest {
+        let PendingData {
+            command,
+            key,
+            options,
+            cas_unique,
+            noreply,
+            meta,
+            meta_flags,
+            ..
+        } = self;
+        match command {
+            StorageCommand::Set => MemcachedRequest::Set {
+                key,
+                value,
+                options,
+                noreply,
+            },
+            StorageCommand::Add => MemcachedRequest::Add {
+                key,
+                value,
+                options,
+                noreply,
+            },
+            StorageCommand::Replace => MemcachedRequest::Replace {
+                key,
+                value,
+                options,
+                noreply,
+            },
+            StorageCommand::Append => MemcachedRequest::Append {
+                key,
+                value,
+                options,
+                noreply,
+            },
+            StorageCommand::Prepend => MemcachedRequest::Prepend {
+                key,
+                value,
+                options,
+                noreply,
+            },
+            StorageCommand::Cas => MemcachedRequest::Cas {
+                key,
+                value,
+                options,
+                cas_unique,
+                noreply,
+            },
+            StorageCommand::MetaSet => MemcachedRequest::MetaSet {
+                key,
+                value,
+                options,
+                meta,
+                flags: meta_flags,
+            },
         }
     }
-    fn map_to_i64(self) -> Result<Option<i64>, std::io::Error>;
-    fn map_to_u64(self) -> Result<Option<u64>, std::io::Error>;
-    fn map_to_u32(self) -> Result<Option<u32>, std::io::Error>;
 }
 
-impl MapToInteger for Option<String> {
-    fn map_to_i64(self) -> Result<Option<i64>, std::io::Error> {
-        match self {
-            None => Ok(None),
-            Some(s) => Self::map_to_integer(s.as_str()),
-        }
-    }
+#[derive(Debug)]
+pub(crate) struct MemcachedCodec {
+    max_line_length: usize,
+    max_item_size: usize,
+    max_key_length: usize,
 
-    fn map_to_u64(self) -> Result<Option<u64>, std::io::Error> {
-        match self {
-            None => Ok(None),
-            Some(s) => Self::map_to_integer(s.as_str()),
-        }
-    }
+    pending: Option<PendingData>,
+    skip_line: bool,
+    swallow: usize,
+}
 
-    fn map_to_u32(self) -> Result<Option<u32>, std::io::Error> {
-        match self {
-            None => Ok(None),
-            Some(s) => Self::map_to_integer(s.as_str()),
+impl MemcachedCodec {
+    pub(crate) fn new(options: &ServerOptions) -> Self {
+        Self {
+            max_line_length: options.max_line_length,
+            max_item_size: options.max_item_size,
+            max_key_length: options.max_key_length,
+            pending: None,
+            skip_line: false,
+            swallow: 0,
         }
     }
 }
 
-#[derive(Debug, Default)]
-pub(crate) struct MemcachedCodec {
-    command: Option<String>,
-    key: Option<String>,
-
-    // write
-    flags: Option<u32>,
-    expire: Option<u64>,
-    number_of_bytes: Option<u64>,
-
-    // diff
-    diff: Option<i64>,
+impl Default for MemcachedCodec {
+    fn default() -> Self {
+        Self::new(&ServerOptions::default())
+    }
 }
 
 impl MemcachedCodec {
+    fn encode_value(value: MemcachedValue, dst: &mut BytesMut) -> Result<(), std::fmt::Error> {
+        let MemcachedValue {
+            key,
+            flags,
+            value,
+            cas,
+        } = value;
+        write!(dst, "VALUE {key} {flags} {}", value.len())?;
+        if let Some(cas) = cas {
+            write!(dst, " {cas}")?;
+        }
+        dst.write_str("\r\n")?;
+        dst.put_slice(&value);
+        dst.write_str("\r\n")
+    }
+
+    fn encode_meta(meta: MetaResponse, dst: &mut BytesMut) -> Result<(), std::fmt::Error> {
+        dst.write_str(meta.status.code())?;
+        if let Some(value) = &meta.value {
+            write!(dst, " {}", value.len())?;
+        }
+        for flag in &meta.flags {
+            write!(dst, " {flag}")?;
+        }
+        dst.write_str("\r\n")?;
+        if let Some(value) = meta.value {
+            dst.put_slice(&value);
+            dst.write_str("\r\n")?;
+        }
+        Ok(())
+    }
+
     fn encode_data(
         item: Result<MemcachedResponse, MemcachedError>,
         dst: &mut BytesMut,
     ) -> Result<(), std::fmt::Error> {
         match item {
             Ok(res) => match res {
+                MemcachedResponse::Ok => dst.write_str("OK\r\n"),
                 MemcachedResponse::Stored => dst.write_str("STORED\r\n"),
                 MemcachedResponse::Deleted => dst.write_str("DELETED\r\n"),
+                MemcachedResponse::Touched => dst.write_str("TOUCHED\r\n"),
+                MemcachedResponse::Reset => dst.write_str("RESET\r\n"),
                 MemcachedResponse::NoValue => dst.write_str("END\r\n"),
-                MemcachedResponse::Value {
-                    key,
-                    value,
-                    expire,
-                    flags,
-                } => {
-                    write!(
-                        dst,
-                        "VALUE {key} {flags} {}\r\n{value}\r\nEND\r\n",
-                        expire.as_secs()
-                    )
+                MemcachedResponse::Value(value) => {
+                    Self::encode_value(value, dst)?;
+                    dst.write_str("END\r\n")
+                }
+                MemcachedResponse::Values(values) => {
+                    for value in values {
+                        Self::encode_value(value, dst)?;
+                    }
+                    dst.write_str("END\r\n")
                 }
+                MemcachedResponse::Number(value) => write!(dst, "{value}\r\n"),
                 MemcachedResponse::Statistics(stats) => {
                     for (key, value) in stats {
                         let msg = format!("STAT {key} {value}\r\n");
                         dst.write_str(msg.as_str())?;
                     }
-                    Ok(())
+                    dst.write_str("END\r\n")
                 }
                 MemcachedResponse::Version(version) => {
                     let msg = format!("VERSION {version}\r\n");
                     dst.write_str(msg.as_str())
                 }
+                MemcachedResponse::Item(item) => {
+                    Self::encode_value(item.value, dst)?;
+                    dst.write_str("END\r\n")
+                }
+                MemcachedResponse::Meta(meta) => Self::encode_meta(meta, dst),
             },
             Err(err) => match err {
                 MemcachedError::NoExistenceCommand => dst.write_str("ERROR\r\n"),
@@ -140,11 +226,11 @@ impl MemcachedCodec {
                     let msg = format!("SERVER_ERROR {message}\r\n");
                     dst.write_str(msg.as_str())
                 }
-                MemcachedError::NotFound => dst.write_str("CLIENT_ERROR Not found\r\n"),
-                MemcachedError::AlreadyExists => dst.write_str("CLIENT_ERROR Already exists\r\n"),
-                MemcachedError::FailedToParseInteger => {
-                    dst.write_str("CLIENT_ERROR Failed to parse integer\r\n")
-                }
+                MemcachedError::NotFound => dst.write_str("NOT_FOUND\r\n"),
+                MemcachedError::Exists => dst.write_str("EXISTS\r\n"),
+                MemcachedError::NotStored => dst.write_str("NOT_STORED\r\n"),
+                MemcachedError::FailedToParseInteger => dst
+                    .write_str("CLIENT_ERROR cannot increment or decrement non-numeric value\r\n"),
             },
         }
     }
@@ -163,250 +249,1130 @@ impl Encoder<Result<MemcachedResponse, MemcachedError>> for MemcachedCodec {
     }
 }
 
-trait PositionSpace {
-    fn position_space(&self) -> Option<usize>;
-    fn substring_spaced(&mut self) -> std::io::Result<Option<String>>;
-    fn position_newline(&self) -> Option<usize>;
-    fn substring_newlined(&mut self) -> std::io::Result<Option<String>>;
+fn bad_command_line() -> MemcachedError {
+    MemcachedError::Client("bad command line format".to_string())
 }
 
-impl PositionSpace for BytesMut {
-    fn position_space(&self) -> Option<usize> {
-        self.iter().position(|v| *v == b' ')
-    }
+fn line_too_long() -> MemcachedError {
+    MemcachedError::Client("line too long".to_string())
+}
 
-    fn substring_spaced(&mut self) -> std::io::Result<Option<String>> {
-        let index_of_space = self.position_space();
-        let data = match index_of_space {
-            None => return Ok(None),
-            Some(index) => self.split_to(index),
-        };
-        self.advance(1);
+fn parse_token<T: FromStr>(token: &str) -> Result<T, MemcachedError> {
+    T::from_str(token).map_err(|_| bad_command_line())
+}
 
-        let value = String::from_utf8(data.to_vec())
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+fn parse_exptime(token: &str) -> Result<i64, MemcachedError> {
+    i64::from_str(token).map_err(|_| MemcachedError::Client("invalid exptime argument".to_string()))
+}
 
-        Ok(Some(value.trim_end().to_string()))
-    }
+/// Keys are 1..=`max_key_length` bytes with no spaces or control characters.
+pub(crate) fn is_valid_key(key: &[u8], max_key_length: usize) -> bool {
+    !key.is_empty() && key.len() <= max_key_length && key.iter().all(|b| *b > b' ' && *b != 0x7f)
+}
 
-    fn position_newline(&self) -> Option<usize> {
-        self.iter().position(|v| *v == b'\n')
+fn split_noreply<'a, 'b>(tokens: &'a [&'b str]) -> (&'a [&'b str], bool) {
+    match tokens.split_last() {
+        Some((&"noreply", rest)) => (rest, true),
+        _ => (tokens, false),
     }
+}
 
-    fn substring_newlined(&mut self) -> std::io::Result<Option<String>> {
-        let index_of_space = self.position_newline();
-        let data = match index_of_space {
-            None => return Ok(None),
-            Some(index) => self.split_to(index),
-        };
-        self.advance(1);
+trait TakeLine {
+    fn take_line(&mut self) -> Option<BytesMut>;
+    fn skip_line(&mut self) -> bool;
+}
 
-        let value = String::from_utf8(data.to_vec())
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+impl TakeLine for BytesMut {
+    fn take_line(&mut self) -> Option<BytesMut> {
+        let index = self.iter().position(|v| *v == b'\n')?;
+        let mut line = self.split_to(index);
+        self.advance(1);
+        if line.last() == Some(&b'\r') {
+            line.truncate(line.len() - 1);
+        }
+        Some(line)
+    }
 
-        Ok(Some(value.trim_end().to_string()))
+    fn skip_line(&mut self) -> bool {
+        match self.iter().position(|v| *v == b'\n') {
+            Some(index) => {
+                self.advance(index + 1);
+                true
+            }
+            None => {
+                self.clear();
+                false
+            }
+        }
     }
 }
 
+/// Longest data block the decoder will swallow after rejecting its header. memcached refuses
+/// lengths past this as well; anything longer is not a real data block, so the decoder
+/// resynchronizes on the next line instead of discarding the rest of the connection.
+const MAX_SWALLOW_LENGTH: usize = i32::MAX as usize - 2;
+
 impl MemcachedCodec {
-    fn decode_key_request(&mut self, src: &mut BytesMut) -> std::io::Result<Option<String>> {
-        self.key.clone().or_else_result(|| src.substring_newlined())
+    fn swallow_data_block(&mut self, number_of_bytes: usize) {
+        if number_of_bytes <= MAX_SWALLOW_LENGTH {
+            self.swallow = number_of_bytes + 2;
+        }
     }
 
-    fn decode_diff_request(
-        &mut self,
-        src: &mut BytesMut,
-    ) -> std::io::Result<Option<(String, i64)>> {
-        let Some(key) = self.key.clone().or_else_result(|| src.substring_spaced())? else {
-            return Ok(None);
-        };
-        self.key = Some(key.to_string());
-
-        let Some(diff) = self
-            .diff
-            .or_else_result(|| src.substring_newlined()?.map_to_i64())?
-        else {
-            return Ok(None);
-        };
-        self.diff = Some(diff);
+    fn parse_key(&self, key: &str) -> Result<String, MemcachedError> {
+        if !is_valid_key(key.as_bytes(), self.max_key_length) {
+            return Err(bad_command_line());
+        }
+        Ok(key.to_string())
+    }
 
-        Ok(Some((key, diff)))
+    fn parse_keys(&self, keys: &[&str]) -> Result<Vec<String>, MemcachedError> {
+        keys.iter().map(|key| self.parse_key(key)).collect()
     }
 
-    fn decode_write_request(
+    fn decode_storage_command(
         &mut self,
-        src: &mut BytesMut,
-    ) -> std::io::Result<Option<(String, WriteOptions, String)>> {
-        let Some(key) = self.key.clone().or_else_result(|| src.substring_spaced())? else {
-            return Ok(None);
+        command: StorageCommand,
+        tokens: &[&str],
+    ) -> Result<(), MemcachedError> {
+        let (tokens, noreply) = split_noreply(tokens);
+        let (key, flags, expire, number_of_bytes, cas_unique) = match (command, tokens) {
+            (StorageCommand::Cas, [key, flags, expire, number_of_bytes, cas_unique]) => {
+                (key, flags, expire, number_of_bytes, Some(cas_unique))
+            }
+            (StorageCommand::Cas, _) => return Err(MemcachedError::NoExistenceCommand),
+            (_, [key, flags, expire, number_of_bytes]) => {
+                (key, flags, expire, number_of_bytes, None)
+            }
+            _ => return Err(MemcachedError::NoExistenceCommand),
         };
-        self.key = Some(key.to_string());
-
-        let options = {
-            let Some(flags) = self
-                .flags
-                .or_else_result(|| src.substring_spaced()?.map_to_u32())?
-            else {
-                return Ok(None);
-            };
-            self.flags = Some(flags);
 
-            let Some(expire_in_seconds) = self
-                .expire
-                .or_else_result(|| src.substring_spaced()?.map_to_u64())?
-            else {
-                return Ok(None);
+        let number_of_bytes: usize = parse_token(number_of_bytes)?;
+        let header = || -> Result<(String, WriteOptions, u64), MemcachedError> {
+            let key = self.parse_key(key)?;
+            let options = WriteOptions {
+                flags: parse_token(flags)?,
+                expire: parse_token(expire)?,
             };
-            self.expire = Some(expire_in_seconds);
-
-            WriteOptions {
-                flags,
-                expire: Duration::from_secs(expire_in_seconds),
+            let cas_unique = cas_unique.map(|v| parse_token(v)).transpose()?;
+            Ok((key, options, cas_unique.unwrap_or(0)))
+        };
+        let (key, options, cas_unique) = match header() {
+            Ok(v) => v,
+            Err(e) => {
+                // The data block length is known, so drop it rather than parse it as a command.
+                self.swallow_data_block(number_of_bytes);
+                return Err(e);
             }
         };
+        if number_of_bytes > self.max_item_size {
+            self.swallow_data_block(number_of_bytes);
+            return Err(MemcachedError::Server(
+                "object too large for cache".to_string(),
+            ));
+        }
+        self.pending = Some(PendingData {
+            command,
+            key,
+            options,
+            number_of_bytes,
+            cas_unique,
+            noreply,
+            meta: MetaSetOptions::default(),
+            meta_flags: MetaFlags::default(),
+        });
+        Ok(())
+    }
 
-        let Some(number_of_bytes) = self
-            .number_of_bytes
-            .or_else_result(|| src.substring_newlined()?.map_to_u64())?
-        else {
-            return Ok(None);
+    fn decode_meta_set(&mut self, tokens: &[&str]) -> Result<(), MemcachedError> {
+        let [key, number_of_bytes, flags @ ..] = tokens else {
+            return Err(MemcachedError::NoExistenceCommand);
         };
-        self.number_of_bytes = Some(number_of_bytes);
-        let number_of_bytes = number_of_bytes as usize;
-
-        debug!("length: src: {}, count: {number_of_bytes}", src.len());
-        if src.len() < number_of_bytes {
-            return Ok(None);
+        let number_of_bytes: usize = parse_token(number_of_bytes)?;
+        let header =
+            || -> Result<(String, MetaFlags, WriteOptions, MetaSetOptions), MemcachedError> {
+                let key = self.parse_key(key)?;
+                let flags = MetaFlags::parse(MetaCommand::Set, flags)?;
+                let (options, meta) = flags.set_options()?;
+                Ok((key, flags, options, meta))
+            };
+        let (key, meta_flags, options, meta) = match header() {
+            Ok(v) => v,
+            Err(e) => {
+                self.swallow_data_block(number_of_bytes);
+                return Err(e);
+            }
+        };
+        if number_of_bytes > self.max_item_size {
+            self.swallow_data_block(number_of_bytes);
+            return Err(MemcachedError::Server(
+                "object too large for cache".to_string(),
+            ));
         }
-        let bytes = src.split_to(number_of_bytes);
-        let value = String::from_utf8(bytes.to_vec())
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-        Ok(Some((key, options, value)))
+        self.pending = Some(PendingData {
+            command: StorageCommand::MetaSet,
+            key,
+            options,
+            number_of_bytes,
+            cas_unique: 0,
+            noreply: false,
+            meta,
+            meta_flags,
+        });
+        Ok(())
     }
 
-    fn decode_impl(
+    fn decode_command_line(
         &mut self,
-        src: &mut BytesMut,
-    ) -> Result<Option<MemcachedRequest>, std::io::Error> {
-        debug!("decode: {}", src.len());
-        let Some(cmd) = self
-            .command
-            .clone()
-            .or_else_result(|| src.substring_spaced())?
-        else {
-            return Ok(None);
+        line: &[u8],
+    ) -> Result<Option<MemcachedRequest>, MemcachedError> {
+        let line = std::str::from_utf8(line).map_err(|_| bad_command_line())?;
+        let tokens: Vec<&str> = line.split_ascii_whitespace().collect();
+        let Some((&command, args)) = tokens.split_first() else {
+            return Err(MemcachedError::NoExistenceCommand);
         };
-        self.command = Some(cmd.to_string());
-
-        match cmd.trim() {
-            "set" => match self.decode_write_request(src) {
-                Ok(v) => match v {
-                    Some((key, options, value)) => Ok(Some(MemcachedRequest::Set {
-                        key,
-                        options,
-                        value,
-                    })),
-                    None => Ok(None),
-                },
-                Err(e) => Err(e),
-            },
-            "add" => match self.decode_write_request(src) {
-                Ok(v) => match v {
-                    Some((key, options, value)) => Ok(Some(MemcachedRequest::Add {
-                        key,
-                        options,
-                        value,
-                    })),
-                    None => Ok(None),
-                },
-                Err(e) => Err(e),
-            },
-            "replace" => match self.decode_write_request(src) {
-                Ok(v) => match v {
-                    Some((key, options, value)) => Ok(Some(MemcachedRequest::Replace {
-                        key,
-                        options,
-                        value,
-                    })),
-                    None => Ok(None),
-                },
-                Err(e) => Err(e),
-            },
-            "append" => match self.decode_write_request(src) {
-                Ok(v) => match v {
-                    Some((key, options, value)) => Ok(Some(MemcachedRequest::Append {
-                        key,
-                        options,
-                        value,
-                    })),
-                    None => Ok(None),
-                },
-                Err(e) => Err(e),
-            },
-            "prepend" => match self.decode_write_request(src) {
-                Ok(v) => match v {
-                    Some((key, options, value)) => Ok(Some(MemcachedRequest::Prepend {
-                        key,
-                        options,
-                        value,
-                    })),
-                    None => Ok(None),
+
+        if let Some(storage_command) = StorageCommand::from_name(command) {
+            self.decode_storage_command(storage_command, args)?;
+            return Ok(None);
+        }
+        if command == "ms" {
+            self.decode_meta_set(args)?;
+            return Ok(None);
+        }
+
+        let request = match command {
+            "get" | "gets" => {
+                if args.is_empty() {
+                    return Err(MemcachedError::NoExistenceCommand);
+                }
+                let keys = self.parse_keys(args)?;
+                if command == "get" {
+                    MemcachedRequest::Get { keys }
+                } else {
+                    MemcachedRequest::Gets { keys }
+                }
+            }
+            "delete" => match split_noreply(args) {
+                ([key], noreply) | ([key, "0"], noreply) => MemcachedRequest::Delete {
+                    key: self.parse_key(key)?,
+                    noreply,
                 },
-                Err(e) => Err(e),
+                ([_, _], _) => {
+                    return Err(MemcachedError::Client(
+                        "bad command line format.  Usage: delete <key> [noreply]".to_string(),
+                    ))
+                }
+                _ => return Err(MemcachedError::NoExistenceCommand),
             },
-            "get" => match self.decode_key_request(src) {
-                Ok(v) => match v {
-                    Some(key) => Ok(Some(MemcachedRequest::Get { key })),
-                    None => Ok(None),
+            "incr" | "decr" => {
+                let ([key, diff], noreply) = split_noreply(args) else {
+                    return Err(MemcachedError::NoExistenceCommand);
+                };
+                let key = self.parse_key(key)?;
+                let diff = u64::from_str(diff).map_err(|_| {
+                    MemcachedError::Client("invalid numeric delta argument".to_string())
+                })?;
+                if command == "incr" {
+                    MemcachedRequest::Incr { key, diff, noreply }
+                } else {
+                    MemcachedRequest::Decr { key, diff, noreply }
+                }
+            }
+            "touch" => {
+                let ([key, expire], noreply) = split_noreply(args) else {
+                    return Err(MemcachedError::NoExistenceCommand);
+                };
+                MemcachedRequest::Touch {
+                    key: self.parse_key(key)?,
+                    expire: parse_exptime(expire)?,
+                    noreply,
+                }
+            }
+            "gat" | "gats" => {
+                let [expire, keys @ ..] = args else {
+                    return Err(MemcachedError::NoExistenceCommand);
+                };
+                if keys.is_empty() {
+                    return Err(MemcachedError::NoExistenceCommand);
+                }
+                let expire = parse_exptime(expire)?;
+                let keys = self.parse_keys(keys)?;
+                if command == "gat" {
+                    MemcachedRequest::Gat { expire, keys }
+                } else {
+                    MemcachedRequest::Gats { expire, keys }
+                }
+            }
+            "flush_all" => match split_noreply(args) {
+                ([], noreply) => MemcachedRequest::FlushAll { delay: 0, noreply },
+                ([delay], noreply) => MemcachedRequest::FlushAll {
+                    delay: parse_token(delay)?,
+                    noreply,
                 },
-                Err(e) => Err(e),
+                _ => return Err(MemcachedError::NoExistenceCommand),
             },
-            "delete" => match self.decode_key_request(src) {
-                Ok(v) => match v {
-                    Some(key) => Ok(Some(MemcachedRequest::Delete { key })),
-                    None => Ok(None),
+            "stats" => match args {
+                [] => MemcachedRequest::Stats { subcommand: None },
+                [subcommand] => MemcachedRequest::Stats {
+                    subcommand: Some(subcommand.to_string()),
                 },
-                Err(e) => Err(e),
+                _ => return Err(MemcachedError::NoExistenceCommand),
             },
-            "incr" => match self.decode_diff_request(src) {
-                Ok(v) => match v {
-                    Some((key, diff)) => Ok(Some(MemcachedRequest::Incr { key, diff })),
-                    None => Ok(None),
+            "version" => MemcachedRequest::Version,
+            "verbosity" => match split_noreply(args) {
+                ([level], noreply) => MemcachedRequest::Verbosity {
+                    level: parse_token(level)?,
+                    noreply,
                 },
-                Err(e) => Err(e),
+                _ => return Err(MemcachedError::NoExistenceCommand),
             },
-            "decr" => match self.decode_diff_request(src) {
-                Ok(v) => match v {
-                    Some((key, diff)) => Ok(Some(MemcachedRequest::Decr { key, diff })),
-                    None => Ok(None),
+            "mg" | "md" | "ma" => {
+                let [key, flags @ ..] = args else {
+                    return Err(MemcachedError::NoExistenceCommand);
+                };
+                let key = self.parse_key(key)?;
+                match command {
+                    "mg" => {
+                        let flags = MetaFlags::parse(MetaCommand::Get, flags)?;
+                        MemcachedRequest::MetaGet {
+                            key,
+                            touch: flags.touch()?,
+                            flags,
+                        }
+                    }
+                    "md" => {
+                        let flags = MetaFlags::parse(MetaCommand::Delete, flags)?;
+                        MemcachedRequest::MetaDelete {
+                            key,
+                            meta: flags.delete_options()?,
+                            flags,
+                        }
+                    }
+                    _ => {
+                        let flags = MetaFlags::parse(MetaCommand::Arithmetic, flags)?;
+                        MemcachedRequest::MetaArithmetic {
+                            key,
+                            meta: flags.arithmetic_options()?,
+                            flags,
+                        }
+                    }
+                }
+            }
+            "me" => match args {
+                [key] => MemcachedRequest::MetaDebug {
+                    key: self.parse_key(key)?,
                 },
-                Err(e) => Err(e),
+                _ => return Err(MemcachedError::NoExistenceCommand),
             },
-            "stats" => Ok(Some(MemcachedRequest::Stats)),
-            "version" => Ok(Some(MemcachedRequest::Version)),
+            "mn" => MemcachedRequest::MetaNoop,
+            "quit" => MemcachedRequest::Quit,
+            "shutdown" => MemcachedRequest::Shutdown,
             c => {
                 warn!("Unsupported command: {c}");
-                Ok(Some(MemcachedRequest::Unsupported))
+                MemcachedRequest::Unsupported
             }
+        };
+        Ok(Some(request))
+    }
+
+    fn decode_data_block(
+        &mut self,
+        src: &mut BytesMut,
+        pending: PendingData,
+    ) -> Option<Result<MemcachedRequest, MemcachedError>> {
+        let number_of_bytes = pending.number_of_bytes;
+        debug!("length: src: {}, count: {number_of_bytes}", src.len());
+        if src.len() < number_of_bytes + 2 {
+            src.reserve(number_of_bytes + 2 - src.len());
+            self.pending = Some(pending);
+            return None;
+        }
+
+        let value = src.split_to(number_of_bytes).freeze();
+        if &src[..2] != b"\r\n" {
+            self.skip_line = !src.skip_line();
+            return Some(Err(MemcachedError::Client("bad data chunk".to_string())));
+        }
+        src.advance(2);
+
+        Some(Ok(pending.into_request(value)))
+    }
+}
+
+impl MemcachedCodec {
+    fn reset(&mut self) {
+        self.pending = None;
+    }
+
+    fn decode_impl(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Option<Result<MemcachedRequest, MemcachedError>> {
+        debug!("decode: {}", src.len());
+        if self.swallow > 0 {
+            let count = self.swallow.min(src.len());
+            src.advance(count);
+            self.swallow -= count;
+            if self.swallow > 0 {
+                return None;
+            }
+        }
+        if self.skip_line {
+            self.skip_line = !src.skip_line();
+            if self.skip_line {
+                return None;
+            }
+        }
+        if let Some(pending) = self.pending.take() {
+            return self.decode_data_block(src, pending);
+        }
+
+        let Some(line) = src.take_line() else {
+            if src.len() > self.max_line_length {
+                self.skip_line = !src.skip_line();
+                return Some(Err(line_too_long()));
+            }
+            return None;
+        };
+        if line.len() > self.max_line_length {
+            return Some(Err(line_too_long()));
+        }
+        match self.decode_command_line(&line) {
+            Ok(Some(request)) => Some(Ok(request)),
+            Ok(None) => self.decode_impl(src),
+            Err(e) => Some(Err(e)),
         }
     }
 }
 
 impl Decoder for MemcachedCodec {
-    type Item = MemcachedRequest;
+    type Item = Result<MemcachedRequest, MemcachedError>;
     type Error = std::io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let value = self.decode_impl(src)?;
-        if value.is_some() {
-            self.command = None;
-            self.key = None;
-            self.flags = None;
-            self.expire = None;
-            self.number_of_bytes = None;
-            self.diff = None;
+        let item = self.decode_impl(src);
+        if let Some(Err(e)) = &item {
+            debug!("decode error: {e:?}");
+            self.reset();
+        }
+        Ok(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::{MetaArithmeticMode, MetaSetMode};
+
+    fn encode(item: Result<MemcachedResponse, MemcachedError>) -> BytesMut {
+        let mut dst = BytesMut::new();
+        MemcachedCodec::default()
+            .encode(item, &mut dst)
+            .expect("Can encode");
+        dst
+    }
+
+    fn decode(src: &[u8]) -> Option<Result<MemcachedRequest, MemcachedError>> {
+        let mut src = BytesMut::from(src);
+        MemcachedCodec::default()
+            .decode(&mut src)
+            .expect("Can decode")
+    }
+
+    #[test]
+    fn test_decode_set_noreply() {
+        let request = decode(b"set key 0 0 5 noreply\r\nvalue\r\n");
+        assert!(matches!(
+            request,
+            Some(Ok(MemcachedRequest::Set { key, value, noreply: true, .. }))
+                if key == "key" && value == "value"
+        ));
+    }
+
+    #[test]
+    fn test_decode_delete_noreply() {
+        let request = decode(b"delete key noreply\r\n");
+        assert!(matches!(
+            request,
+            Some(Ok(MemcachedRequest::Delete { key, noreply: true })) if key == "key"
+        ));
+    }
+
+    #[test]
+    fn test_decode_incr_noreply() {
+        let request = decode(b"incr key 5 noreply\r\n");
+        assert!(matches!(
+            request,
+            Some(Ok(MemcachedRequest::Incr { key, diff: 5, noreply: true })) if key == "key"
+        ));
+    }
+
+    #[test]
+    fn test_decode_delete_without_noreply() {
+        let request = decode(b"delete key\r\n");
+        assert!(matches!(
+            request,
+            Some(Ok(MemcachedRequest::Delete { key, noreply: false })) if key == "key"
+        ));
+    }
+
+    #[test]
+    fn test_decode_stats_subcommand() {
+        assert!(matches!(
+            decode(b"stats settings\r\n"),
+            Some(Ok(MemcachedRequest::Stats { subcommand: Some(subcommand) })) if subcommand == "settings"
+        ));
+        assert_eq!(
+            decode(b"stats items extra\r\n").map(Result::unwrap_err),
+            Some(MemcachedError::NoExistenceCommand)
+        );
+    }
+
+    #[test]
+    fn test_decode_meta_commands() {
+        assert!(matches!(
+            decode(b"mg key v c T30 Oop\r\n"),
+            Some(Ok(MemcachedRequest::MetaGet { key, touch: Some(30), flags }))
+                if key == "key" && flags.has('v') && flags.has('c')
+        ));
+        assert!(matches!(
+            decode(b"md key I T5\r\n"),
+            Some(Ok(MemcachedRequest::MetaDelete { key, meta, .. }))
+                if key == "key" && meta.invalidate && meta.expire == Some(5)
+        ));
+        assert!(matches!(
+            decode(b"ma key MD D2\r\n"),
+            Some(Ok(MemcachedRequest::MetaArithmetic { meta, .. }))
+                if meta.mode == MetaArithmeticMode::Decrement && meta.delta == 2
+        ));
+        assert!(matches!(
+            decode(b"me key\r\n"),
+            Some(Ok(MemcachedRequest::MetaDebug { key })) if key == "key"
+        ));
+        assert!(matches!(
+            decode(b"mn\r\n"),
+            Some(Ok(MemcachedRequest::MetaNoop))
+        ));
+        assert_eq!(
+            decode(b"mg\r\n").map(Result::unwrap_err),
+            Some(MemcachedError::NoExistenceCommand)
+        );
+        assert_eq!(
+            decode(b"mg key z\r\n").map(Result::unwrap_err),
+            Some(MemcachedError::Client("invalid flag".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_decode_meta_set() {
+        assert!(matches!(
+            decode(b"ms key 5 F3 T60 ME q\r\nvalue\r\n"),
+            Some(Ok(MemcachedRequest::MetaSet { key, value, options, meta, flags }))
+                if key == "key"
+                    && value == "value"
+                    && options.flags == 3
+                    && options.expire == 60
+                    && meta.mode == MetaSetMode::Add
+                    && flags.has('q')
+        ));
+
+        let mut codec = MemcachedCodec::default();
+        let mut src = BytesMut::from(&b"ms key 5 Fx\r\nvalue\r\nmn\r\n"[..]);
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Err(MemcachedError::Client(_)))
+        ));
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::MetaNoop))
+        ));
+
+        for (line, error) in [
+            (
+                format!("ms key {}\r\nmn\r\n", u64::MAX),
+                MemcachedError::Server("object too large for cache".to_string()),
+            ),
+            (
+                format!("ms key {} Fx\r\nmn\r\n", u64::MAX),
+                MemcachedError::Client("bad token in command line format".to_string()),
+            ),
+        ] {
+            let mut src = BytesMut::from(line.as_bytes());
+            assert_eq!(
+                codec
+                    .decode(&mut src)
+                    .expect("Can decode")
+                    .map(Result::unwrap_err),
+                Some(error),
+                "{line:?}"
+            );
+            assert!(matches!(
+                codec.decode(&mut src).expect("Can decode"),
+                Some(Ok(MemcachedRequest::MetaNoop))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decode_admin_commands() {
+        assert!(matches!(
+            decode(b"verbosity 2 noreply\r\n"),
+            Some(Ok(MemcachedRequest::Verbosity {
+                level: 2,
+                noreply: true
+            }))
+        ));
+        assert_eq!(
+            decode(b"verbosity loud\r\n").map(Result::unwrap_err),
+            Some(bad_command_line())
+        );
+        assert!(matches!(
+            decode(b"quit\r\n"),
+            Some(Ok(MemcachedRequest::Quit))
+        ));
+        assert!(matches!(
+            decode(b"shutdown\r\n"),
+            Some(Ok(MemcachedRequest::Shutdown))
+        ));
+    }
+
+    #[test]
+    fn test_decode_commands_without_arguments() {
+        assert!(matches!(
+            decode(b"stats\r\n"),
+            Some(Ok(MemcachedRequest::Stats { subcommand: None }))
+        ));
+        assert!(matches!(
+            decode(b"version\r\n"),
+            Some(Ok(MemcachedRequest::Version))
+        ));
+    }
+
+    #[test]
+    fn test_decode_get_multiple_keys() {
+        assert!(matches!(
+            decode(b"get a b  c\r\n"),
+            Some(Ok(MemcachedRequest::Get { keys })) if keys == ["a", "b", "c"]
+        ));
+    }
+
+    #[test]
+    fn test_decode_waits_for_complete_data_block() {
+        let mut codec = MemcachedCodec::default();
+        let mut src = BytesMut::from(&b"set key 0 0 5\r\nval"[..]);
+        assert!(codec.decode(&mut src).expect("Can decode").is_none());
+
+        src.put_slice(b"ue\r");
+        assert!(codec.decode(&mut src).expect("Can decode").is_none());
+
+        src.put_slice(b"\nget key\r\n");
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Set { value, .. })) if value == "value"
+        ));
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Get { keys })) if keys == ["key"]
+        ));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_decode_bad_data_chunk() {
+        let mut codec = MemcachedCodec::default();
+        let mut src = BytesMut::from(&b"set key 0 0 3\r\nvalue\r\nversion\r\n"[..]);
+        assert_eq!(
+            codec
+                .decode(&mut src)
+                .expect("Can decode")
+                .map(Result::unwrap_err),
+            Some(MemcachedError::Client("bad data chunk".to_string()))
+        );
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Version))
+        ));
+    }
+
+    #[test]
+    fn test_decode_bad_command_line() {
+        assert_eq!(
+            decode(b"set key x 0 5\r\n").map(Result::unwrap_err),
+            Some(MemcachedError::Client(
+                "bad command line format".to_string()
+            ))
+        );
+        assert_eq!(
+            decode(b"incr key\r\n").map(Result::unwrap_err),
+            Some(MemcachedError::NoExistenceCommand)
+        );
+    }
+
+    #[test]
+    fn test_decode_bad_storage_header_drops_data_block() {
+        let mut codec = MemcachedCodec::default();
+        let mut src = BytesMut::from(&b"set key x 0 5\r\nvalue\r\nversion\r\n"[..]);
+        assert_eq!(
+            codec
+                .decode(&mut src)
+                .expect("Can decode")
+                .map(Result::unwrap_err),
+            Some(MemcachedError::Client(
+                "bad command line format".to_string()
+            ))
+        );
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Version))
+        ));
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_decode_non_utf8_command_line() {
+        let mut codec = MemcachedCodec::default();
+        let mut src = BytesMut::from(&b"get \xff\xfe\r\nversion\r\n"[..]);
+        assert_eq!(
+            codec
+                .decode(&mut src)
+                .expect("Can decode")
+                .map(Result::unwrap_err),
+            Some(MemcachedError::Client(
+                "bad command line format".to_string()
+            ))
+        );
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Version))
+        ));
+    }
+
+    #[test]
+    fn test_is_valid_key() {
+        assert!(is_valid_key(b"key", 250));
+        assert!(is_valid_key("ключ".as_bytes(), 250));
+        assert!(is_valid_key(&[b'k'; 250], 250));
+        assert!(!is_valid_key(&[b'k'; 251], 250));
+        assert!(!is_valid_key(b"", 250));
+        assert!(!is_valid_key(b"k\x0bey", 250));
+        assert!(!is_valid_key(b"k\x00ey", 250));
+        assert!(!is_valid_key(b"k\x7fey", 250));
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_keys() {
+        let long_key = "k".repeat(251);
+        for line in [
+            format!("get {long_key}\r\n"),
+            "get key k\x01y\r\n".to_string(),
+            "gets k\x0by\r\n".to_string(),
+            "delete k\x01y\r\n".to_string(),
+            "incr k\x01y 1\r\n".to_string(),
+            "touch k\x01y 1\r\n".to_string(),
+            "gat 1 k\x01y\r\n".to_string(),
+            "mg k\x01y v\r\n".to_string(),
+            "md k\x01y\r\n".to_string(),
+            "ma k\x01y\r\n".to_string(),
+            "me k\x01y\r\n".to_string(),
+        ] {
+            assert_eq!(
+                decode(line.as_bytes()).map(Result::unwrap_err),
+                Some(bad_command_line()),
+                "{line:?}"
+            );
         }
-        Ok(value)
+    }
+
+    #[test]
+    fn test_decode_invalid_storage_key_drops_data_block() {
+        let mut codec = MemcachedCodec::default();
+        let long_key = "k".repeat(251);
+        let mut src = BytesMut::from(
+            format!("set {long_key} 0 0 5\r\nvalue\r\nms k\x01y 3\r\nabc\r\nversion\r\n")
+                .as_bytes(),
+        );
+
+        for _ in 0..2 {
+            assert_eq!(
+                codec
+                    .decode(&mut src)
+                    .expect("Can decode")
+                    .map(Result::unwrap_err),
+                Some(bad_command_line())
+            );
+        }
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Version))
+        ));
+    }
+
+    #[test]
+    fn test_decode_invalid_storage_key_with_huge_length() {
+        let mut codec = MemcachedCodec::default();
+        let mut src =
+            BytesMut::from(format!("set k\x01y 0 0 {}\r\nversion\r\n", u64::MAX).as_bytes());
+        assert_eq!(
+            codec
+                .decode(&mut src)
+                .expect("Can decode")
+                .map(Result::unwrap_err),
+            Some(bad_command_line())
+        );
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Version))
+        ));
+    }
+
+    #[test]
+    fn test_decode_key_length_is_configurable() {
+        let mut codec = MemcachedCodec::new(&ServerOptions {
+            max_key_length: 3,
+            ..ServerOptions::default()
+        });
+        let mut src = BytesMut::from(&b"get key\r\nget keys\r\n"[..]);
+
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Get { .. }))
+        ));
+        assert_eq!(
+            codec
+                .decode(&mut src)
+                .expect("Can decode")
+                .map(Result::unwrap_err),
+            Some(bad_command_line())
+        );
+    }
+
+    fn limited_codec() -> MemcachedCodec {
+        MemcachedCodec::new(&ServerOptions {
+            max_line_length: 16,
+            max_item_size: 8,
+            ..ServerOptions::default()
+        })
+    }
+
+    #[test]
+    fn test_decode_line_too_long_without_newline() {
+        let mut codec = limited_codec();
+        let mut src = BytesMut::from(&b"get aaaaaaaaaaaaaaaaaaaa"[..]);
+        assert_eq!(
+            codec
+                .decode(&mut src)
+                .expect("Can decode")
+                .map(Result::unwrap_err),
+            Some(MemcachedError::Client("line too long".to_string()))
+        );
+        assert!(src.is_empty());
+
+        src.put_slice(b"aaaa\r\nversion\r\n");
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Version))
+        ));
+    }
+
+    #[test]
+    fn test_decode_line_too_long_with_newline() {
+        let mut codec = limited_codec();
+        let mut src = BytesMut::from(&b"get aaaaaaaaaaaaaaaaaaaa\r\nversion\r\n"[..]);
+        assert_eq!(
+            codec
+                .decode(&mut src)
+                .expect("Can decode")
+                .map(Result::unwrap_err),
+            Some(MemcachedError::Client("line too long".to_string()))
+        );
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Version))
+        ));
+    }
+
+    #[test]
+    fn test_decode_object_too_large() {
+        let mut codec = limited_codec();
+        let mut src = BytesMut::from(&b"set key 0 0 10\r\n01234"[..]);
+        assert_eq!(
+            codec
+                .decode(&mut src)
+                .expect("Can decode")
+                .map(Result::unwrap_err),
+            Some(MemcachedError::Server(
+                "object too large for cache".to_string()
+            ))
+        );
+        assert!(codec.decode(&mut src).expect("Can decode").is_none());
+
+        src.put_slice(b"56789\r\nversion\r\n");
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Version))
+        ));
+    }
+
+    #[test]
+    fn test_decode_object_too_large_with_huge_length() {
+        let mut codec = MemcachedCodec::default();
+        let mut src = BytesMut::from(format!("set k 0 0 {}\r\nversion\r\n", u64::MAX).as_bytes());
+        assert_eq!(
+            codec
+                .decode(&mut src)
+                .expect("Can decode")
+                .map(Result::unwrap_err),
+            Some(MemcachedError::Server(
+                "object too large for cache".to_string()
+            ))
+        );
+        assert!(matches!(
+            codec.decode(&mut src).expect("Can decode"),
+            Some(Ok(MemcachedRequest::Version))
+        ));
+    }
+
+    #[test]
+    fn test_decode_flush_all() {
+        assert!(matches!(
+            decode(b"flush_all\r\n"),
+            Some(Ok(MemcachedRequest::FlushAll {
+                delay: 0,
+                noreply: false
+            }))
+        ));
+        assert!(matches!(
+            decode(b"flush_all 30 noreply\r\n"),
+            Some(Ok(MemcachedRequest::FlushAll {
+                delay: 30,
+                noreply: true
+            }))
+        ));
+        assert!(matches!(
+            decode(b"flush_all noreply\r\n"),
+            Some(Ok(MemcachedRequest::FlushAll {
+                delay: 0,
+                noreply: true
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_encode_ok() {
+        assert_eq!(encode(Ok(MemcachedResponse::Ok)), "OK\r\n");
+    }
+
+    #[test]
+    fn test_encode_stored() {
+        assert_eq!(encode(Ok(MemcachedResponse::Stored)), "STORED\r\n");
+    }
+
+    #[test]
+    fn test_encode_deleted() {
+        assert_eq!(encode(Ok(MemcachedResponse::Deleted)), "DELETED\r\n");
+    }
+
+    #[test]
+    fn test_encode_touched() {
+        assert_eq!(encode(Ok(MemcachedResponse::Touched)), "TOUCHED\r\n");
+    }
+
+    #[test]
+    fn test_encode_meta() {
+        let value = MetaResponse {
+            status: MetaStatus::Value,
+            flags: vec!["c1".to_string(), "kkey".to_string()],
+            value: Some(Bytes::from("value")),
+            quiet: false,
+        };
+        assert_eq!(
+            encode(Ok(MemcachedResponse::Meta(value))),
+            "VA 5 c1 kkey\r\nvalue\r\n"
+        );
+        let miss = MetaResponse {
+            status: MetaStatus::Miss,
+            flags: vec![],
+            value: None,
+            quiet: false,
+        };
+        assert_eq!(encode(Ok(MemcachedResponse::Meta(miss))), "EN\r\n");
+    }
+
+    #[test]
+    fn test_encode_reset() {
+        assert_eq!(encode(Ok(MemcachedResponse::Reset)), "RESET\r\n");
+    }
+
+    #[test]
+    fn test_decode_touch() {
+        assert!(matches!(
+            decode(b"touch key 10 noreply\r\n"),
+            Some(Ok(MemcachedRequest::Touch { key, expire: 10, noreply: true })) if key == "key"
+        ));
+        assert_eq!(
+            decode(b"touch key soon\r\n").map(Result::unwrap_err),
+            Some(MemcachedError::Client(
+                "invalid exptime argument".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_decode_gat() {
+        assert!(matches!(
+            decode(b"gat 10 a b\r\n"),
+            Some(Ok(MemcachedRequest::Gat { expire: 10, keys })) if keys == ["a", "b"]
+        ));
+        assert!(matches!(
+            decode(b"gats -1 a\r\n"),
+            Some(Ok(MemcachedRequest::Gats { expire: -1, keys })) if keys == ["a"]
+        ));
+        assert_eq!(
+            decode(b"gat 10\r\n").map(Result::unwrap_err),
+            Some(MemcachedError::NoExistenceCommand)
+        );
+    }
+
+    #[test]
+    fn test_encode_no_value() {
+        assert_eq!(encode(Ok(MemcachedResponse::NoValue)), "END\r\n");
+    }
+
+    #[test]
+    fn test_encode_value() {
+        let value = MemcachedValue {
+            key: "key".to_string(),
+            flags: 42,
+            value: Bytes::from("hello world"),
+            cas: None,
+        };
+        assert_eq!(
+            encode(Ok(MemcachedResponse::Value(value))),
+            "VALUE key 42 11\r\nhello world\r\nEND\r\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_value_with_cas() {
+        let value = MemcachedValue {
+            key: "key".to_string(),
+            flags: 0,
+            value: Bytes::from("hello"),
+            cas: Some(7),
+        };
+        assert_eq!(
+            encode(Ok(MemcachedResponse::Value(value))),
+            "VALUE key 0 5 7\r\nhello\r\nEND\r\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_values() {
+        let values = vec![
+            MemcachedValue {
+                key: "key1".to_string(),
+                flags: 1,
+                value: Bytes::from("a"),
+                cas: None,
+            },
+            MemcachedValue {
+                key: "key2".to_string(),
+                flags: 2,
+                value: Bytes::from_static(b"b\r\nc"),
+                cas: None,
+            },
+        ];
+        assert_eq!(
+            encode(Ok(MemcachedResponse::Values(values))),
+            "VALUE key1 1 1\r\na\r\nVALUE key2 2 4\r\nb\r\nc\r\nEND\r\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_statistics() {
+        let stats = vec![
+            ("pid".to_string(), "1".to_string()),
+            ("uptime".to_string(), "2".to_string()),
+        ];
+        assert_eq!(
+            encode(Ok(MemcachedResponse::Statistics(stats))),
+            "STAT pid 1\r\nSTAT uptime 2\r\nEND\r\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_number() {
+        assert_eq!(
+            encode(Ok(MemcachedResponse::Number(u64::MAX))),
+            "18446744073709551615\r\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_failed_to_parse_integer() {
+        assert_eq!(
+            encode(Err(MemcachedError::FailedToParseInteger)),
+            "CLIENT_ERROR cannot increment or decrement non-numeric value\r\n"
+        );
+    }
+
+    #[test]
+    fn test_decode_incr_invalid_delta() {
+        assert_eq!(
+            decode(b"incr key -1\r\n").map(Result::unwrap_err),
+            Some(MemcachedError::Client(
+                "invalid numeric delta argument".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_encode_version() {
+        assert_eq!(
+            encode(Ok(MemcachedResponse::Version("1.0.0".to_string()))),
+            "VERSION 1.0.0\r\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_error() {
+        assert_eq!(encode(Err(MemcachedError::NoExistenceCommand)), "ERROR\r\n");
+    }
+
+    #[test]
+    fn test_encode_client_error() {
+        assert_eq!(
+            encode(Err(MemcachedError::Client("bad".to_string()))),
+            "CLIENT_ERROR bad\r\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_server_error() {
+        assert_eq!(
+            encode(Err(MemcachedError::Server("busy".to_string()))),
+            "SERVER_ERROR busy\r\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_not_stored() {
+        assert_eq!(encode(Err(MemcachedError::NotStored)), "NOT_STORED\r\n");
+    }
+
+    #[test]
+    fn test_encode_not_found() {
+        assert_eq!(encode(Err(MemcachedError::NotFound)), "NOT_FOUND\r\n");
+    }
+
+    #[test]
+    fn test_encode_exists() {
+        assert_eq!(encode(Err(MemcachedError::Exists)), "EXISTS\r\n");
+    }
+
+    #[test]
+    fn test_encode_get_miss() {
+        assert_eq!(encode(Ok(MemcachedResponse::Values(vec![]))), "END\r\n");
     }
 }
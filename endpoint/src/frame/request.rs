@@ -1,47 +1,138 @@
-use crate::handler::WriteOptions;
+use super::meta::MetaFlags;
+use crate::handler::{MetaArithmeticOptions, MetaDeleteOptions, MetaSetOptions, WriteOptions};
+use bytes::Bytes;
 
 #[derive(Debug)]
 pub(crate) enum MemcachedRequest {
     Set {
         key: String,
-        value: String,
+        value: Bytes,
         options: WriteOptions,
+        noreply: bool,
     },
     Add {
         key: String,
-        value: String,
+        value: Bytes,
         options: WriteOptions,
+        noreply: bool,
     },
     Replace {
         key: String,
-        value: String,
+        value: Bytes,
         options: WriteOptions,
+        noreply: bool,
     },
     Append {
         key: String,
-        value: String,
+        value: Bytes,
         options: WriteOptions,
+        noreply: bool,
     },
     Prepend {
         key: String,
-        value: String,
+        value: Bytes,
         options: WriteOptions,
+        noreply: bool,
     },
-    Get {
+    Cas {
         key: String,
+        value: Bytes,
+        options: WriteOptions,
+        cas_unique: u64,
+        noreply: bool,
+    },
+    Get {
+        keys: Vec<String>,
+    },
+    Gets {
+        keys: Vec<String>,
     },
     Delete {
         key: String,
+        noreply: bool,
     },
     Incr {
         key: String,
-        diff: i64,
+        diff: u64,
+        noreply: bool,
     },
     Decr {
         key: String,
-        diff: i64,
+        diff: u64,
+        noreply: bool,
+    },
+    Touch {
+        key: String,
+        expire: i64,
+        noreply: bool,
+    },
+    Gat {
+        expire: i64,
+        keys: Vec<String>,
+    },
+    Gats {
+        expire: i64,
+        keys: Vec<String>,
+    },
+    FlushAll {
+        delay: i64,
+        noreply: bool,
+    },
+    Stats {
+        subcommand: Option<String>,
     },
-    Stats,
     Version,
+    Verbosity {
+        level: u32,
+        noreply: bool,
+    },
+    Quit,
+    Shutdown,
+    MetaGet {
+        key: String,
+        touch: Option<i64>,
+        flags: MetaFlags,
+    },
+    MetaSet {
+        key: String,
+        value: Bytes,
+        options: WriteOptions,
+        meta: MetaSetOptions,
+        flags: MetaFlags,
+    },
+    MetaDelete {
+        key: String,
+        meta: MetaDeleteOptions,
+        flags: MetaFlags,
+    },
+    MetaArithmetic {
+        key: String,
+        meta: MetaArithmeticOptions,
+        flags: MetaFlags,
+    },
+    MetaDebug {
+        key: String,
+    },
+    MetaNoop,
     Unsupported,
 }
+
+impl MemcachedRequest {
+    pub(crate) fn noreply(&self) -> bool {
+        match self {
+            MemcachedRequest::Set { noreply, .. }
+            | MemcachedRequest::Add { noreply, .. }
+            | MemcachedRequest::Replace { noreply, .. }
+            | MemcachedRequest::Append { noreply, .. }
+            | MemcachedRequest::Prepend { noreply, .. }
+            | MemcachedRequest::Cas { noreply, .. }
+            | MemcachedRequest::Delete { noreply, .. }
+            | MemcachedRequest::Incr { noreply, .. }
+            | MemcachedRequest::Decr { noreply, .. }
+            | MemcachedRequest::Touch { noreply, .. }
+            | MemcachedRequest::FlushAll { noreply, .. }
+            | MemcachedRequest::Verbosity { noreply, .. } => *noreply,
+            _ => false,
+        }
+    }
+}
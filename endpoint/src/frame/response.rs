@@ -1,27 +1,89 @@
-use std::collections::HashMap;
-use std::time::Duration;
+use bytes::Bytes;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum MemcachedError {
     NoExistenceCommand,
     NotFound,
-    AlreadyExists,
+    NotStored,
+    Exists,
     FailedToParseInteger,
     Client(String),
     Server(String),
 }
 
+#[derive(Debug, Eq, PartialEq)]
+pub struct MemcachedValue {
+    pub key: String,
+    pub flags: u32,
+    pub value: Bytes,
+    pub cas: Option<u64>,
+}
+
+/// An item together with the metadata the meta commands can ask for.
+#[derive(Debug, Eq, PartialEq)]
+pub struct MemcachedItem {
+    pub value: MemcachedValue,
+    /// Remaining seconds to live, `None` if the item never expires.
+    pub ttl: Option<u64>,
+    /// Seconds since the item was last fetched.
+    pub last_access: u64,
+    /// The item was invalidated and is served until it is recached.
+    pub stale: bool,
+    /// This fetch won the right to recache a stale item.
+    pub win: bool,
+    /// Another client already won the right to recache.
+    pub claimed: bool,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum MetaStatus {
+    Value,
+    Success,
+    Miss,
+    NotFound,
+    NotStored,
+    Exists,
+    Noop,
+    Debug,
+}
+
+impl MetaStatus {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            MetaStatus::Value => "VA",
+            MetaStatus::Success => "HD",
+            MetaStatus::Miss => "EN",
+            MetaStatus::NotFound => "NF",
+            MetaStatus::NotStored => "NS",
+            MetaStatus::Exists => "EX",
+            MetaStatus::Noop => "MN",
+            MetaStatus::Debug => "ME",
+        }
+    }
+}
+
+/// A meta protocol reply: status code, return flags and an optional data block.
+#[derive(Debug, Eq, PartialEq)]
+pub struct MetaResponse {
+    pub(crate) status: MetaStatus,
+    pub(crate) flags: Vec<String>,
+    pub(crate) value: Option<Bytes>,
+    pub(crate) quiet: bool,
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum MemcachedResponse {
+    Ok,
     Stored,
     Deleted,
+    Touched,
+    Reset,
     NoValue,
-    Value {
-        key: String,
-        flags: u32,
-        expire: Duration,
-        value: String,
-    },
-    Statistics(HashMap<String, String>),
+    Value(MemcachedValue),
+    Values(Vec<MemcachedValue>),
+    Number(u64),
+    Statistics(Vec<(String, String)>),
     Version(String),
+    Item(MemcachedItem),
+    Meta(MetaResponse),
 }
@@ -0,0 +1,374 @@
+use crate::frame::binary::{opcode, status, BinaryCodec, BinaryRequest, BinaryResponse};
+use crate::frame::{MemcachedError, MemcachedItem, MemcachedResponse, MemcachedValue};
+use crate::handler::{
+    MemcachedHandler, MemcachedResult, MetaArithmeticMode, MetaArithmeticOptions,
+    MetaDeleteOptions, MetaSetMode, MetaSetOptions, WriteOptions,
+};
+use crate::server::ServerOptions;
+use crate::statistics::{statistics_reply, ServerStatistics, VERSION};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{FutureExt, SinkExt};
+use log::{trace, warn};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+use tokio_util::sync::CancellationToken;
+
+/// Incr/decr exptime that means "do not create a missing counter".
+const NO_AUTOVIVIFY: u32 = 0xffff_ffff;
+
+fn is_quiet(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        opcode::GETQ
+            | opcode::GETKQ
+            | opcode::GATQ
+            | opcode::GATKQ
+            | opcode::SETQ
+            | opcode::ADDQ
+            | opcode::REPLACEQ
+            | opcode::DELETEQ
+            | opcode::INCREMENTQ
+            | opcode::DECREMENTQ
+            | opcode::QUITQ
+            | opcode::FLUSHQ
+            | opcode::APPENDQ
+            | opcode::PREPENDQ
+    )
+}
+
+fn is_get(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        opcode::GET
+            | opcode::GETQ
+            | opcode::GETK
+            | opcode::GETKQ
+            | opcode::GAT
+            | opcode::GATQ
+            | opcode::GATK
+            | opcode::GATKQ
+    )
+}
+
+fn returns_key(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        opcode::GETK | opcode::GETKQ | opcode::GATK | opcode::GATKQ
+    )
+}
+
+fn invalid_arguments() -> MemcachedError {
+    MemcachedError::Client("Invalid arguments".to_string())
+}
+
+fn error_response(response: BinaryResponse, error: MemcachedError) -> BinaryResponse {
+    match error {
+        MemcachedError::NotFound => response.error(status::KEY_NOT_FOUND, "Not found"),
+        MemcachedError::Exists => response.error(status::KEY_EXISTS, "Data exists for key."),
+        MemcachedError::NotStored => response.error(status::ITEM_NOT_STORED, "Not stored."),
+        MemcachedError::FailedToParseInteger => response.error(
+            status::NON_NUMERIC_VALUE,
+            "Non-numeric server-side value for incr or decr",
+        ),
+        MemcachedError::NoExistenceCommand => {
+            response.error(status::UNKNOWN_COMMAND, "Unknown command")
+        }
+        MemcachedError::Client(message) => response.error(status::INVALID_ARGUMENTS, &message),
+        MemcachedError::Server(message) => response.error(status::INTERNAL_ERROR, &message),
+    }
+}
+
+fn expect_item(res: MemcachedResult) -> Result<MemcachedItem, MemcachedError> {
+    match res? {
+        MemcachedResponse::Item(item) => Ok(item),
+        res => Err(MemcachedError::Server(format!(
+            "unexpected response {res:?}"
+        ))),
+    }
+}
+
+fn expect_value(res: MemcachedResult) -> Result<MemcachedValue, MemcachedError> {
+    match res? {
+        MemcachedResponse::Value(value) => Ok(value),
+        res => Err(MemcachedError::Server(format!(
+            "unexpected response {res:?}"
+        ))),
+    }
+}
+
+fn expect_values(res: MemcachedResult) -> Result<Vec<MemcachedValue>, MemcachedError> {
+    match res? {
+        MemcachedResponse::Values(values) => Ok(values),
+        res => Err(MemcachedError::Server(format!(
+            "unexpected response {res:?}"
+        ))),
+    }
+}
+
+/// Checks the packet layout for an opcode: extras length, and whether a key or value is allowed.
+fn validate(
+    request: &BinaryRequest,
+    extras: &[usize],
+    key: bool,
+    value: bool,
+) -> Result<(), MemcachedError> {
+    if !extras.contains(&request.extras.len())
+        || request.key.is_empty() == key
+        || (!value && !request.value.is_empty())
+    {
+        return Err(invalid_arguments());
+    }
+    Ok(())
+}
+
+async fn dispatch(
+    handler: &dyn MemcachedHandler,
+    options: &ServerOptions,
+    statistics: &ServerStatistics,
+    request: BinaryRequest,
+) -> Vec<BinaryResponse> {
+    let response = BinaryResponse::to(&request);
+    let Ok(key) = std::str::from_utf8(&request.key).map(str::to_string) else {
+        return vec![error_response(response, invalid_arguments())];
+    };
+
+    if request.opcode == opcode::STAT {
+        let subcommand = (!key.is_empty()).then_some(key.as_str());
+        return match statistics_reply(handler, subcommand, options, statistics).await {
+            Ok(MemcachedResponse::Statistics(stats)) => stats
+                .into_iter()
+                .map(|(key, value)| BinaryResponse {
+                    key: Bytes::from(key),
+                    value: Bytes::from(value),
+                    ..BinaryResponse::to(&request)
+                })
+                .chain(std::iter::once(response))
+                .collect(),
+            Ok(_) => vec![response],
+            Err(e) => vec![error_response(response, e)],
+        };
+    }
+
+    let opcode = request.opcode;
+    let res = dispatch_one(handler, key, &request, response).await;
+    match res {
+        Ok(_) if is_quiet(opcode) && !is_get(opcode) => vec![],
+        Ok(response) => vec![response],
+        Err(MemcachedError::NotFound) if is_quiet(opcode) && is_get(opcode) => vec![],
+        Err(e) => {
+            let mut response = error_response(BinaryResponse::to(&request), e);
+            if returns_key(opcode) {
+                response.key = request.key.clone();
+            }
+            vec![response]
+        }
+    }
+}
+
+async fn dispatch_one(
+    handler: &dyn MemcachedHandler,
+    key: String,
+    request: &BinaryRequest,
+    mut response: BinaryResponse,
+) -> Result<BinaryResponse, MemcachedError> {
+    let mut extras = request.extras.clone();
+    match request.opcode {
+        opcode::GET | opcode::GETQ | opcode::GETK | opcode::GETKQ => {
+            validate(request, &[0], true, false)?;
+            let value = expect_value(handler.get(key).await)?;
+            Ok(value_response(request, response, value))
+        }
+        opcode::GAT | opcode::GATQ | opcode::GATK | opcode::GATKQ => {
+            validate(request, &[4], true, false)?;
+            let expire = extras.get_u32() as i64;
+            let values = expect_values(handler.get_and_touch(vec![key], expire).await)?;
+            let value = values.into_iter().next().ok_or(MemcachedError::NotFound)?;
+            Ok(value_response(request, response, value))
+        }
+        opcode::SET
+        | opcode::SETQ
+        | opcode::ADD
+        | opcode::ADDQ
+        | opcode::REPLACE
+        | opcode::REPLACEQ => {
+            validate(request, &[8], true, true)?;
+            let options = WriteOptions {
+                flags: extras.get_u32(),
+                expire: extras.get_u32() as i64,
+            };
+            let mode = match request.opcode {
+                opcode::SET | opcode::SETQ => MetaSetMode::Set,
+                opcode::ADD | opcode::ADDQ => MetaSetMode::Add,
+                _ => MetaSetMode::Replace,
+            };
+            let meta = MetaSetOptions {
+                mode,
+                compare_cas: (mode != MetaSetMode::Add && request.cas != 0).then_some(request.cas),
+                invalidate: false,
+            };
+            let item = expect_item(
+                handler
+                    .meta_set(key, request.value.clone(), options, meta)
+                    .await,
+            )?;
+            response.cas = item.value.cas.unwrap_or(0);
+            Ok(response)
+        }
+        opcode::APPEND | opcode::APPENDQ | opcode::PREPEND | opcode::PREPENDQ => {
+            validate(request, &[0], true, true)?;
+            let options = WriteOptions {
+                flags: 0,
+                expire: 0,
+            };
+            let mode = match request.opcode {
+                opcode::APPEND | opcode::APPENDQ => MetaSetMode::Append,
+                _ => MetaSetMode::Prepend,
+            };
+            let meta = MetaSetOptions {
+                mode,
+                compare_cas: (request.cas != 0).then_some(request.cas),
+                invalidate: false,
+            };
+            let item = expect_item(
+                handler
+                    .meta_set(key, request.value.clone(), options, meta)
+                    .await,
+            )?;
+            response.cas = item.value.cas.unwrap_or(0);
+            Ok(response)
+        }
+        opcode::DELETE | opcode::DELETEQ => {
+            validate(request, &[0], true, false)?;
+            let meta = MetaDeleteOptions {
+                compare_cas: (request.cas != 0).then_some(request.cas),
+                ..MetaDeleteOptions::default()
+            };
+            handler.meta_delete(key, meta).await?;
+            Ok(response)
+        }
+        opcode::INCREMENT | opcode::INCREMENTQ | opcode::DECREMENT | opcode::DECREMENTQ => {
+            validate(request, &[20], true, false)?;
+            let delta = extras.get_u64();
+            let initial = extras.get_u64();
+            let expire = extras.get_u32();
+            let meta = MetaArithmeticOptions {
+                mode: match request.opcode {
+                    opcode::INCREMENT | opcode::INCREMENTQ => MetaArithmeticMode::Increment,
+                    _ => MetaArithmeticMode::Decrement,
+                },
+                delta,
+                initial,
+                autovivify: (expire != NO_AUTOVIVIFY).then_some(expire as i64),
+                compare_cas: (request.cas != 0).then_some(request.cas),
+            };
+            let item = expect_item(handler.meta_arithmetic(key, meta).await)?;
+            let number = std::str::from_utf8(&item.value.value)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .ok_or(MemcachedError::FailedToParseInteger)?;
+            let mut value = BytesMut::with_capacity(8);
+            value.put_u64(number);
+            response.value = value.freeze();
+            response.cas = item.value.cas.unwrap_or(0);
+            Ok(response)
+        }
+        opcode::TOUCH => {
+            validate(request, &[4], true, false)?;
+            handler.touch(key, extras.get_u32() as i64).await?;
+            Ok(response)
+        }
+        opcode::FLUSH | opcode::FLUSHQ => {
+            validate(request, &[0, 4], false, false)?;
+            let delay = if extras.is_empty() {
+                0
+            } else {
+                extras.get_u32() as i64
+            };
+            handler.flush(delay).await?;
+            Ok(response)
+        }
+        opcode::NOOP | opcode::QUIT | opcode::QUITQ => {
+            validate(request, &[0], false, false)?;
+            Ok(response)
+        }
+        opcode::VERSION => {
+            validate(request, &[0], false, false)?;
+            response.value = Bytes::from_static(VERSION.as_bytes());
+            Ok(response)
+        }
+        _ => Err(MemcachedError::NoExistenceCommand),
+    }
+}
+
+fn value_response(
+    request: &BinaryRequest,
+    mut response: BinaryResponse,
+    value: MemcachedValue,
+) -> BinaryResponse {
+    let mut extras = BytesMut::with_capacity(4);
+    extras.put_u32(value.flags);
+    response.extras = extras.freeze();
+    response.cas = value.cas.unwrap_or(0);
+    response.value = value.value;
+    if returns_key(request.opcode) {
+        response.key = request.key.clone();
+    }
+    response
+}
+
+pub(crate) async fn handle_binary<S: AsyncRead + AsyncWrite + Unpin>(
+    mut framed: Framed<S, BinaryCodec>,
+    handler: Arc<dyn MemcachedHandler>,
+    options: ServerOptions,
+    statistics: Arc<ServerStatistics>,
+    shutdown: CancellationToken,
+) -> std::io::Result<()> {
+    loop {
+        let request = tokio::select! {
+            biased;
+            _ = shutdown.cancelled() => break,
+            request = framed.next() => request,
+        };
+        let Some(mut request) = request else {
+            break;
+        };
+
+        // Answer every packet that is already readable, then flush the whole batch at once.
+        let quit = loop {
+            match request? {
+                Ok(request) => {
+                    trace!("Binary request handling: {:?}", request);
+                    let quit = matches!(request.opcode, opcode::QUIT | opcode::QUITQ);
+                    let responses =
+                        dispatch(handler.as_ref(), &options, &statistics, request).await;
+                    for response in responses {
+                        framed.feed(response).await?;
+                    }
+                    if quit {
+                        break true;
+                    }
+                }
+                Err(response) => {
+                    warn!("Invalid binary request: {response:?}");
+                    framed.feed(response).await?;
+                }
+            }
+            if shutdown.is_cancelled() || !options.batch_replies {
+                break false;
+            }
+            match framed.next().now_or_never() {
+                Some(Some(next)) => request = next,
+                Some(None) => break true,
+                None => break false,
+            }
+        };
+        framed.flush().await?;
+        if quit {
+            break;
+        }
+    }
+
+    Ok(())
+}
@@ -1,73 +1,273 @@
-use crate::frame::{MemcachedCodec, MemcachedRequest, MemcachedResponse};
-use crate::handler::MemcachedHandler;
+use crate::frame::binary::{BinaryCodec, REQUEST_MAGIC};
+use crate::frame::{
+    debug_reply, noop_reply, MemcachedCodec, MemcachedRequest, MemcachedResponse, MemcachedValue,
+    MetaCommand,
+};
+use crate::handle_binary::handle_binary;
+use crate::handler::{MemcachedHandler, MemcachedResult};
+use crate::server::ServerOptions;
+use crate::statistics::{statistics_reply, CountingStream, ServerStatistics, VERSION};
 use crate::MemcachedError;
-use futures::SinkExt;
-use log::{debug, trace, warn};
+use futures::{FutureExt, SinkExt};
+use log::{debug, trace, warn, LevelFilter};
 use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
+use tokio_util::sync::CancellationToken;
 
-pub(super) async fn handle_socket(socket: TcpStream, handler: Arc<dyn MemcachedHandler>) {
-    match handle_socket_impl(socket, handler).await {
+pub(super) async fn handle_socket(
+    socket: TcpStream,
+    handler: Arc<dyn MemcachedHandler>,
+    options: ServerOptions,
+    statistics: Arc<ServerStatistics>,
+    shutdown: CancellationToken,
+) {
+    let _connection = statistics.connect();
+    match handle_socket_impl(socket, handler, options, statistics, shutdown).await {
         Ok(_) => debug!("Handle request success"),
         Err(e) => warn!("Handle request error: {e}"),
     }
 }
 
+fn with_cas(res: MemcachedResponse, cas: bool) -> MemcachedResponse {
+    match res {
+        MemcachedResponse::Values(values) => MemcachedResponse::Values(
+            values
+                .into_iter()
+                .map(|value| MemcachedValue {
+                    cas: if cas {
+                        Some(value.cas.unwrap_or(0))
+                    } else {
+                        None
+                    },
+                    ..value
+                })
+                .collect(),
+        ),
+        res => res,
+    }
+}
+
+fn verbosity_filter(level: u32) -> LevelFilter {
+    match level {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Warn,
+        2 => LevelFilter::Info,
+        3 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+fn is_quiet_reply(res: &Result<MemcachedResponse, MemcachedError>) -> bool {
+    matches!(res, Ok(MemcachedResponse::Meta(meta)) if meta.quiet)
+}
+
+fn is_error_reply(res: &Result<MemcachedResponse, MemcachedError>) -> bool {
+    matches!(
+        res,
+        Err(MemcachedError::Client(_)) | Err(MemcachedError::Server(_))
+    )
+}
+
 async fn handle_socket_impl(
     socket: TcpStream,
     handler: Arc<dyn MemcachedHandler>,
+    options: ServerOptions,
+    statistics: Arc<ServerStatistics>,
+    shutdown: CancellationToken,
 ) -> std::io::Result<()> {
-    let mut framed = Framed::new(socket, MemcachedCodec::default());
+    // Replies are already batched per flush, so Nagle would only delay the tail of a batch.
+    socket.set_nodelay(true)?;
 
-    while let Some(request) = framed.next().await {
-        let request = match request {
-            Ok(r) => r,
-            Err(e) => {
-                warn!("Invalid input data: {e}");
-                let res = Err(MemcachedError::Client("Invalid data".to_string()));
-                framed.send(res).await?;
-                continue;
-            }
+    // The first byte of a binary protocol request is always the request magic.
+    let mut first = [0; 1];
+    let peeked = tokio::select! {
+        _ = shutdown.cancelled() => return Ok(()),
+        peeked = socket.peek(&mut first) => peeked?,
+    };
+    if peeked == 0 {
+        return Ok(());
+    }
+
+    let socket = CountingStream::new(socket, statistics.clone());
+    if first[0] == REQUEST_MAGIC {
+        let framed = Framed::new(socket, BinaryCodec::new(&options));
+        handle_binary(framed, handler, options, statistics, shutdown).await
+    } else {
+        let framed = Framed::new(socket, MemcachedCodec::new(&options));
+        handle_text(framed, handler, options, statistics, shutdown).await
+    }
+}
+
+/// Answers one request; `None` means the client asked to close the connection.
+async fn dispatch(
+    handler: &dyn MemcachedHandler,
+    options: &ServerOptions,
+    statistics: &ServerStatistics,
+    shutdown: &CancellationToken,
+    request: MemcachedRequest,
+) -> Option<MemcachedResult> {
+    let res = match request {
+        MemcachedRequest::Set {
+            key,
+            value,
+            options,
+            ..
+        } => handler.set(key, value, options).await,
+        MemcachedRequest::Add {
+            key,
+            value,
+            options,
+            ..
+        } => handler.add(key, value, options).await,
+        MemcachedRequest::Replace {
+            key,
+            value,
+            options,
+            ..
+        } => handler.replace(key, value, options).await,
+        MemcachedRequest::Append {
+            key,
+            value,
+            options,
+            ..
+        } => handler.append(key, value, options).await,
+        MemcachedRequest::Prepend {
+            key,
+            value,
+            options,
+            ..
+        } => handler.prepend(key, value, options).await,
+        MemcachedRequest::Cas {
+            key,
+            value,
+            options,
+            cas_unique,
+            ..
+        } => handler.cas(key, value, options, cas_unique).await,
+        MemcachedRequest::Get { keys } => handler
+            .get_multi(keys)
+            .await
+            .map(|res| with_cas(res, false)),
+        MemcachedRequest::Gets { keys } => {
+            handler.get_multi(keys).await.map(|res| with_cas(res, true))
+        }
+        MemcachedRequest::Delete { key, .. } => handler.delete(key).await,
+        MemcachedRequest::Touch { key, expire, .. } => handler.touch(key, expire).await,
+        MemcachedRequest::Gat { expire, keys } => handler
+            .get_and_touch(keys, expire)
+            .await
+            .map(|res| with_cas(res, false)),
+        MemcachedRequest::Gats { expire, keys } => handler
+            .get_and_touch(keys, expire)
+            .await
+            .map(|res| with_cas(res, true)),
+        MemcachedRequest::Incr { key, diff, .. } => handler.increment(key, diff).await,
+        MemcachedRequest::Decr { key, diff, .. } => handler.decrement(key, diff).await,
+        MemcachedRequest::FlushAll { delay, .. } => handler.flush(delay).await,
+        MemcachedRequest::Stats { subcommand } => {
+            statistics_reply(handler, subcommand.as_deref(), options, statistics).await
+        }
+        MemcachedRequest::Version => Ok(MemcachedResponse::Version(VERSION.to_string())),
+        MemcachedRequest::Verbosity { level, .. } => {
+            log::set_max_level(verbosity_filter(level));
+            Ok(MemcachedResponse::Ok)
+        }
+        MemcachedRequest::Quit => return None,
+        MemcachedRequest::Shutdown if options.enable_shutdown => {
+            shutdown.cancel();
+            Ok(MemcachedResponse::Ok)
+        }
+        MemcachedRequest::Shutdown => Err(MemcachedError::NoExistenceCommand),
+        MemcachedRequest::MetaGet { key, touch, flags } => {
+            let res = handler.meta_get(key.clone(), touch).await;
+            flags.reply(MetaCommand::Get, &key, res)
+        }
+        MemcachedRequest::MetaSet {
+            key,
+            value,
+            options,
+            meta,
+            flags,
+        } => {
+            let res = handler.meta_set(key.clone(), value, options, meta).await;
+            flags.reply(MetaCommand::Set, &key, res)
+        }
+        MemcachedRequest::MetaDelete { key, meta, flags } => {
+            let res = handler.meta_delete(key.clone(), meta).await;
+            flags.reply(MetaCommand::Delete, &key, res)
+        }
+        MemcachedRequest::MetaArithmetic { key, meta, flags } => {
+            let res = handler.meta_arithmetic(key.clone(), meta).await;
+            flags.reply(MetaCommand::Arithmetic, &key, res)
+        }
+        MemcachedRequest::MetaDebug { key } => {
+            let res = handler.meta_debug(key.clone()).await;
+            debug_reply(&key, res)
+        }
+        MemcachedRequest::MetaNoop => noop_reply(),
+        MemcachedRequest::Unsupported => Err(MemcachedError::NoExistenceCommand),
+    };
+    Some(res)
+}
+
+async fn handle_text<S: AsyncRead + AsyncWrite + Unpin>(
+    mut framed: Framed<S, MemcachedCodec>,
+    handler: Arc<dyn MemcachedHandler>,
+    options: ServerOptions,
+    statistics: Arc<ServerStatistics>,
+    shutdown: CancellationToken,
+) -> std::io::Result<()> {
+    loop {
+        let request = tokio::select! {
+            biased;
+            _ = shutdown.cancelled() => break,
+            request = framed.next() => request,
+        };
+        let Some(mut request) = request else {
+            break;
         };
-        trace!("Request handling: {:?}", request);
 
-        let res = match request {
-            MemcachedRequest::Set {
-                key,
-                value,
-                options,
-            } => handler.set(key, value, options).await,
-            MemcachedRequest::Add {
-                key,
-                value,
-                options,
-            } => handler.add(key, value, options).await,
-            MemcachedRequest::Replace {
-                key,
-                value,
-                options,
-            } => handler.replace(key, value, options).await,
-            MemcachedRequest::Append {
-                key,
-                value,
-                options,
-            } => handler.append(key, value, options).await,
-            MemcachedRequest::Prepend {
-                key,
-                value,
-                options,
-            } => handler.prepend(key, value, options).await,
-            MemcachedRequest::Get { key } => handler.get(key).await,
-            MemcachedRequest::Delete { key } => handler.delete(key).await,
-            MemcachedRequest::Incr { key, diff } => handler.increment(key, diff).await,
-            MemcachedRequest::Decr { key, diff } => handler.decrement(key, diff).await,
-            MemcachedRequest::Stats => handler.statistics().await,
-            MemcachedRequest::Version => Ok(MemcachedResponse::Version("0.1.0".to_string())),
-            MemcachedRequest::Unsupported => Err(MemcachedError::NoExistenceCommand),
+        // Answer every request that is already readable, then flush the whole batch at once.
+        let quit = loop {
+            let res = match request? {
+                Ok(request) => {
+                    trace!("Request handling: {:?}", request);
+                    let noreply = request.noreply();
+                    let Some(res) =
+                        dispatch(handler.as_ref(), &options, &statistics, &shutdown, request).await
+                    else {
+                        break true;
+                    };
+                    if is_quiet_reply(&res) || (noreply && !is_error_reply(&res)) {
+                        None
+                    } else {
+                        Some(res)
+                    }
+                }
+                Err(e) => {
+                    warn!("Invalid request: {e:?}");
+                    Some(Err(e))
+                }
+            };
+            if let Some(res) = res {
+                framed.feed(res).await?;
+            }
+            if shutdown.is_cancelled() || !options.batch_replies {
+                break false;
+            }
+            match framed.next().now_or_never() {
+                Some(Some(next)) => request = next,
+                Some(None) => break true,
+                None => break false,
+            }
         };
-        framed.send(res).await?;
+        framed.flush().await?;
+        if quit {
+            break;
+        }
     }
 
     Ok(())
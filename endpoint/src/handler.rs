@@ -1,26 +1,206 @@
 use async_trait::async_trait;
-use std::time::Duration;
+use bytes::Bytes;
 
-pub use crate::frame::{MemcachedError, MemcachedResponse};
+pub use crate::frame::{
+    MemcachedError, MemcachedItem, MemcachedResponse, MemcachedValue, MetaResponse,
+};
+
+/// Largest exptime that is still taken as relative seconds (30 days).
+pub const MAX_RELATIVE_EXPIRE: i64 = 60 * 60 * 24 * 30;
 
 #[derive(Debug, Clone)]
 pub struct WriteOptions {
     pub flags: u32,
-    pub expire: Duration,
+    /// Raw exptime as sent by the client; see [`Expiration::from_exptime`].
+    pub expire: i64,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Expiration {
+    Never,
+    /// Unix timestamp in seconds from which the item is invisible.
+    At(u64),
+}
+
+impl Expiration {
+    pub fn from_exptime(exptime: i64, now: u64) -> Self {
+        match exptime {
+            0 => Expiration::Never,
+            e if e < 0 => Expiration::At(0),
+            e if e <= MAX_RELATIVE_EXPIRE => Expiration::At(now + e as u64),
+            e => Expiration::At(e as u64),
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self {
+            Expiration::Never => false,
+            Expiration::At(deadline) => *deadline <= now,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum MetaSetMode {
+    Add,
+    Append,
+    Prepend,
+    Replace,
+    #[default]
+    Set,
+}
+
+/// `ms` options beyond flags and exptime.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MetaSetOptions {
+    pub mode: MetaSetMode,
+    pub compare_cas: Option<u64>,
+    /// Store as stale instead of failing when `compare_cas` is older than the item.
+    pub invalidate: bool,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MetaDeleteOptions {
+    pub compare_cas: Option<u64>,
+    /// Mark the item stale instead of removing it.
+    pub invalidate: bool,
+    /// New exptime for an invalidated item.
+    pub expire: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum MetaArithmeticMode {
+    #[default]
+    Increment,
+    Decrement,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MetaArithmeticOptions {
+    pub mode: MetaArithmeticMode,
+    pub delta: u64,
+    /// Value of an item created on a miss.
+    pub initial: u64,
+    /// Create a missing item with this exptime.
+    pub autovivify: Option<i64>,
+    pub compare_cas: Option<u64>,
+}
+
+impl Default for MetaArithmeticOptions {
+    fn default() -> Self {
+        Self {
+            mode: MetaArithmeticMode::Increment,
+            delta: 1,
+            initial: 0,
+            autovivify: None,
+            compare_cas: None,
+        }
+    }
 }
 
 pub type MemcachedResult = Result<MemcachedResponse, MemcachedError>;
 
 #[async_trait]
 pub trait MemcachedHandler: Send + Sync {
-    async fn set(&self, key: String, value: String, options: WriteOptions) -> MemcachedResult;
-    async fn add(&self, key: String, value: String, options: WriteOptions) -> MemcachedResult;
-    async fn replace(&self, key: String, value: String, options: WriteOptions) -> MemcachedResult;
-    async fn append(&self, key: String, value: String, options: WriteOptions) -> MemcachedResult;
-    async fn prepend(&self, key: String, value: String, options: WriteOptions) -> MemcachedResult;
+    async fn set(&self, key: String, value: Bytes, options: WriteOptions) -> MemcachedResult;
+    async fn add(&self, key: String, value: Bytes, options: WriteOptions) -> MemcachedResult;
+    async fn replace(&self, key: String, value: Bytes, options: WriteOptions) -> MemcachedResult;
+    async fn append(&self, key: String, value: Bytes, options: WriteOptions) -> MemcachedResult;
+    async fn prepend(&self, key: String, value: Bytes, options: WriteOptions) -> MemcachedResult;
+    async fn cas(
+        &self,
+        key: String,
+        value: Bytes,
+        options: WriteOptions,
+        cas_unique: u64,
+    ) -> MemcachedResult;
     async fn get(&self, key: String) -> MemcachedResult;
+
+    async fn get_multi(&self, keys: Vec<String>) -> MemcachedResult {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            match self.get(key).await {
+                Ok(MemcachedResponse::Value(value)) => values.push(value),
+                Ok(_) | Err(MemcachedError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(MemcachedResponse::Values(values))
+    }
+
     async fn delete(&self, key: String) -> MemcachedResult;
-    async fn increment(&self, key: String, diff: i64) -> MemcachedResult;
-    async fn decrement(&self, key: String, diff: i64) -> MemcachedResult;
+    async fn touch(&self, key: String, expire: i64) -> MemcachedResult;
+    async fn get_and_touch(&self, keys: Vec<String>, expire: i64) -> MemcachedResult;
+    async fn increment(&self, key: String, diff: u64) -> MemcachedResult;
+    async fn decrement(&self, key: String, diff: u64) -> MemcachedResult;
+    async fn flush(&self, delay: i64) -> MemcachedResult;
     async fn statistics(&self) -> MemcachedResult;
+
+    /// `mg`: fetches an [`MemcachedItem`], optionally updating its exptime.
+    async fn meta_get(&self, key: String, touch: Option<i64>) -> MemcachedResult;
+    /// `ms`: stores and returns the new [`MemcachedItem`].
+    async fn meta_set(
+        &self,
+        key: String,
+        value: Bytes,
+        options: WriteOptions,
+        meta: MetaSetOptions,
+    ) -> MemcachedResult;
+    async fn meta_delete(&self, key: String, meta: MetaDeleteOptions) -> MemcachedResult;
+    /// `ma`: applies the delta and returns the updated [`MemcachedItem`].
+    async fn meta_arithmetic(&self, key: String, meta: MetaArithmeticOptions) -> MemcachedResult;
+    /// `me`: returns the [`MemcachedItem`] without counting it as an access.
+    async fn meta_debug(&self, key: String) -> MemcachedResult;
+
+    /// Table for `stats <subcommand>`, e.g. `items`, `slabs`, `sizes` or `settings`.
+    async fn statistics_for(&self, _subcommand: &str) -> MemcachedResult {
+        Err(MemcachedError::NoExistenceCommand)
+    }
+
+    /// Zeroes the resettable counters for `stats reset`.
+    async fn reset_statistics(&self) -> MemcachedResult {
+        Ok(MemcachedResponse::Reset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn test_zero_never_expires() {
+        let expiration = Expiration::from_exptime(0, NOW);
+        assert_eq!(expiration, Expiration::Never);
+        assert!(!expiration.is_expired(u64::MAX));
+    }
+
+    #[test]
+    fn test_relative_seconds() {
+        let expiration = Expiration::from_exptime(10, NOW);
+        assert_eq!(expiration, Expiration::At(NOW + 10));
+        assert!(!expiration.is_expired(NOW + 9));
+        assert!(expiration.is_expired(NOW + 10));
+    }
+
+    #[test]
+    fn test_thirty_days_is_still_relative() {
+        let expiration = Expiration::from_exptime(MAX_RELATIVE_EXPIRE, NOW);
+        assert_eq!(expiration, Expiration::At(NOW + MAX_RELATIVE_EXPIRE as u64));
+    }
+
+    #[test]
+    fn test_larger_values_are_absolute() {
+        let expiration = Expiration::from_exptime(NOW as i64 + 100, NOW);
+        assert_eq!(expiration, Expiration::At(NOW + 100));
+
+        let expiration = Expiration::from_exptime(MAX_RELATIVE_EXPIRE + 1, NOW);
+        assert!(expiration.is_expired(NOW));
+    }
+
+    #[test]
+    fn test_negative_expires_immediately() {
+        assert!(Expiration::from_exptime(-1, NOW).is_expired(NOW));
+    }
 }
@@ -1,7 +1,11 @@
+mod clock;
 mod frame;
+mod handle_binary;
 mod handle_socket;
 mod handler;
 mod server;
+mod statistics;
 
+pub use clock::*;
 pub use handler::*;
 pub use server::*;
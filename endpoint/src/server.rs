@@ -1,19 +1,103 @@
 use crate::handle_socket::handle_socket;
 use crate::handler::MemcachedHandler;
+use crate::statistics::ServerStatistics;
 use log::info;
 use std::sync::Arc;
 use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    pub max_line_length: usize,
+    pub max_item_size: usize,
+    pub max_key_length: usize,
+    /// Accept the `shutdown` command, which stops the server gracefully.
+    pub enable_shutdown: bool,
+    /// Flush replies once per batch of already readable requests rather than after each one.
+    pub batch_replies: bool,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            max_line_length: 64 * 1024,
+            max_item_size: 1024 * 1024,
+            max_key_length: 250,
+            enable_shutdown: false,
+            batch_replies: true,
+        }
+    }
+}
+
+impl ServerOptions {
+    pub(crate) fn settings(&self) -> Vec<(String, String)> {
+        vec![
+            ("item_size_max".to_string(), self.max_item_size.to_string()),
+            (
+                "max_key_length".to_string(),
+                self.max_key_length.to_string(),
+            ),
+            (
+                "max_line_length".to_string(),
+                self.max_line_length.to_string(),
+            ),
+            (
+                "shutdown_command".to_string(),
+                if self.enable_shutdown { "yes" } else { "no" }.to_string(),
+            ),
+            (
+                "batch_replies".to_string(),
+                if self.batch_replies { "yes" } else { "no" }.to_string(),
+            ),
+        ]
+    }
+}
 
 pub async fn start_server<A: ToSocketAddrs>(
     address: A,
     handler: Arc<dyn MemcachedHandler>,
+    options: ServerOptions,
 ) -> std::io::Result<()> {
     let listener = TcpListener::bind(address).await?;
+    serve(listener, handler, options).await
+}
 
+pub async fn serve(
+    listener: TcpListener,
+    handler: Arc<dyn MemcachedHandler>,
+    options: ServerOptions,
+) -> std::io::Result<()> {
+    let statistics = Arc::new(ServerStatistics::default());
+    let shutdown = CancellationToken::new();
+    // Every connection holds a sender; `recv` yields `None` once all of them are gone.
+    let (connection_tx, mut connection_rx) = mpsc::channel::<()>(1);
     loop {
-        let (socket, peer_address) = listener.accept().await?;
+        let (socket, peer_address) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.cancelled() => break,
+        };
         info!("Accept socket peer address is {peer_address}");
         let processor_handler = handler.clone();
-        tokio::spawn(async move { handle_socket(socket, processor_handler).await });
+        let processor_options = options.clone();
+        let processor_statistics = statistics.clone();
+        let processor_shutdown = shutdown.clone();
+        let processor_connection = connection_tx.clone();
+        tokio::spawn(async move {
+            handle_socket(
+                socket,
+                processor_handler,
+                processor_options,
+                processor_statistics,
+                processor_shutdown,
+            )
+            .await;
+            drop(processor_connection);
+        });
     }
+
+    info!("Shutting down, waiting for open connections");
+    drop(connection_tx);
+    connection_rx.recv().await;
+    Ok(())
 }
@@ -0,0 +1,203 @@
+use crate::clock::{Clock, SystemClock};
+use crate::frame::MemcachedResponse;
+use crate::handler::{MemcachedHandler, MemcachedResult};
+use crate::server::ServerOptions;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Connection and IO counters kept by the endpoint for `stats`.
+pub(crate) struct ServerStatistics {
+    started_at: Instant,
+    curr_connections: AtomicU64,
+    total_connections: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl Default for ServerStatistics {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            curr_connections: AtomicU64::default(),
+            total_connections: AtomicU64::default(),
+            bytes_read: AtomicU64::default(),
+            bytes_written: AtomicU64::default(),
+        }
+    }
+}
+
+impl ServerStatistics {
+    pub(crate) fn connect(self: &Arc<Self>) -> ConnectionGuard {
+        self.curr_connections.fetch_add(1, Ordering::Relaxed);
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self.clone())
+    }
+
+    fn threads() -> usize {
+        match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+            Ok(RuntimeFlavor::MultiThread) => std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            _ => 1,
+        }
+    }
+
+    pub(crate) fn report(&self) -> Vec<(String, String)> {
+        vec![
+            ("pid".to_string(), std::process::id().to_string()),
+            (
+                "uptime".to_string(),
+                self.started_at.elapsed().as_secs().to_string(),
+            ),
+            ("time".to_string(), SystemClock.now().to_string()),
+            ("version".to_string(), VERSION.to_string()),
+            (
+                "curr_connections".to_string(),
+                self.curr_connections.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "total_connections".to_string(),
+                self.total_connections.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "bytes_read".to_string(),
+                self.bytes_read.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "bytes_written".to_string(),
+                self.bytes_written.load(Ordering::Relaxed).to_string(),
+            ),
+            ("threads".to_string(), Self::threads().to_string()),
+        ]
+    }
+
+    pub(crate) fn reset(&self) {
+        self.total_connections.store(0, Ordering::Relaxed);
+        self.bytes_read.store(0, Ordering::Relaxed);
+        self.bytes_written.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Answers `stats [subcommand]`, combining endpoint and handler statistics.
+pub(crate) async fn statistics_reply(
+    handler: &dyn MemcachedHandler,
+    subcommand: Option<&str>,
+    options: &ServerOptions,
+    statistics: &ServerStatistics,
+) -> MemcachedResult {
+    match subcommand {
+        None => handler
+            .statistics()
+            .await
+            .map(|res| prepend_statistics(statistics.report(), res)),
+        Some("reset") => {
+            statistics.reset();
+            handler.reset_statistics().await
+        }
+        Some("settings") => handler
+            .statistics_for("settings")
+            .await
+            .map(|res| prepend_statistics(options.settings(), res)),
+        Some(subcommand) => handler.statistics_for(subcommand).await,
+    }
+}
+
+/// Puts endpoint-side entries in front of the handler's own statistics.
+pub(crate) fn prepend_statistics(
+    mut entries: Vec<(String, String)>,
+    res: MemcachedResponse,
+) -> MemcachedResponse {
+    match res {
+        MemcachedResponse::Statistics(stats) => {
+            entries.extend(stats);
+            MemcachedResponse::Statistics(entries)
+        }
+        res => res,
+    }
+}
+
+pub(crate) struct ConnectionGuard(Arc<ServerStatistics>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.curr_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Stream wrapper that adds every byte moved through it to `bytes_read` / `bytes_written`.
+pub(crate) struct CountingStream<S> {
+    inner: S,
+    statistics: Arc<ServerStatistics>,
+}
+
+impl<S> CountingStream<S> {
+    pub(crate) fn new(inner: S, statistics: Arc<ServerStatistics>) -> Self {
+        Self { inner, statistics }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        self.statistics
+            .bytes_read
+            .fetch_add(read as u64, Ordering::Relaxed);
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.statistics
+                .bytes_written
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.statistics
+                .bytes_written
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -1,15 +1,18 @@
-use endpoint::start_server;
+use endpoint::{start_server, ServerOptions, SystemClock};
 use std::sync::Arc;
-use storage::HashMapStorage;
+use storage::{HashMapStorage, StorageOptions};
 
 #[tokio::main]
 async fn main() {
     println!("Hello, world!");
     env_logger::init();
 
-    let mem_storage = HashMapStorage::default();
+    let mem_storage =
+        Arc::new(HashMapStorage::new(StorageOptions::default(), Arc::new(SystemClock)).unwrap());
+    mem_storage.start_reaper();
+    mem_storage.start_slab_automove();
 
-    start_server(("localhost", 11211), Arc::new(mem_storage))
+    start_server(("localhost", 11211), mem_storage, ServerOptions::default())
         .await
         .unwrap();
 }
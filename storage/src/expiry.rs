@@ -0,0 +1,96 @@
+use endpoint::Expiration;
+use std::collections::{BTreeMap, HashSet};
+
+/// Keys with a deadline, ordered by deadline, so expired items can be found without a scan.
+#[derive(Default)]
+pub(crate) struct ExpiryIndex {
+    deadlines: BTreeMap<u64, HashSet<String>>,
+}
+
+impl ExpiryIndex {
+    pub(crate) fn insert(&mut self, key: &str, expire: Expiration) {
+        if let Expiration::At(deadline) = expire {
+            self.deadlines
+                .entry(deadline)
+                .or_default()
+                .insert(key.to_string());
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str, expire: Expiration) {
+        let Expiration::At(deadline) = expire else {
+            return;
+        };
+        if let Some(keys) = self.deadlines.get_mut(&deadline) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.deadlines.remove(&deadline);
+            }
+        }
+    }
+
+    /// Removes and returns up to `limit` keys whose deadline is at or before `now`.
+    pub(crate) fn pop_expired(&mut self, now: u64, limit: usize) -> Vec<String> {
+        let mut expired = Vec::new();
+        while expired.len() < limit {
+            let Some(mut entry) = self.deadlines.first_entry() else {
+                break;
+            };
+            if *entry.key() > now {
+                break;
+            }
+            let keys = entry.get_mut();
+            let taken: Vec<String> = keys.iter().take(limit - expired.len()).cloned().collect();
+            for key in &taken {
+                keys.remove(key);
+            }
+            if keys.is_empty() {
+                entry.remove();
+            }
+            expired.extend(taken);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pop_expired_in_deadline_order() {
+        let mut index = ExpiryIndex::default();
+        index.insert("late", Expiration::At(30));
+        index.insert("early", Expiration::At(10));
+        index.insert("never", Expiration::Never);
+        index.insert("now", Expiration::At(20));
+
+        assert_eq!(index.pop_expired(20, 10), vec!["early", "now"]);
+        assert!(index.pop_expired(20, 10).is_empty());
+        assert_eq!(index.pop_expired(u64::MAX, 10), vec!["late"]);
+    }
+
+    #[test]
+    fn test_pop_expired_respects_limit() {
+        let mut index = ExpiryIndex::default();
+        for key in ["a", "b", "c"] {
+            index.insert(key, Expiration::At(10));
+        }
+        index.insert("d", Expiration::At(11));
+
+        assert_eq!(index.pop_expired(11, 2).len(), 2);
+        assert_eq!(index.pop_expired(11, 2).len(), 2);
+        assert!(index.pop_expired(11, 2).is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut index = ExpiryIndex::default();
+        index.insert("key", Expiration::At(10));
+        index.remove("key", Expiration::At(10));
+        index.remove("missing", Expiration::At(10));
+
+        assert!(index.pop_expired(10, 10).is_empty());
+        assert!(index.deadlines.is_empty());
+    }
+}
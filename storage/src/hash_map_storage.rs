@@ -1,151 +1,1512 @@
+use crate::expiry::ExpiryIndex;
+use crate::policy::{EvictionPolicy, EvictionPolicyFactory, Lru};
+use crate::slab::Slabs;
+use crate::statistics::{bump, hit_or_miss, StorageStatistics};
 use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
 use endpoint::{
-    MemcachedError, MemcachedHandler, MemcachedResponse, MemcachedResult, WriteOptions,
+    Clock, Expiration, MemcachedError, MemcachedHandler, MemcachedItem, MemcachedResponse,
+    MemcachedResult, MemcachedValue, MetaArithmeticMode, MetaArithmeticOptions, MetaDeleteOptions,
+    MetaSetMode, MetaSetOptions, ServerOptions, SystemClock, WriteOptions,
 };
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+/// Bucket width of the `stats sizes` histogram.
+const SIZES_BUCKET: u64 = 32;
+
+/// Bookkeeping per copy of a key on top of its bytes: the `String` and the tick or deadline kept
+/// next to it.
+const KEY_COPY_OVERHEAD: u64 = (std::mem::size_of::<String>() + std::mem::size_of::<u64>()) as u64;
+
+/// Bookkeeping per item on top of its key copies and value.
+const ITEM_OVERHEAD: u64 = std::mem::size_of::<McdValue>() as u64;
+
+/// Most copies of a key an item has with the built-in policies: the shard map, SLRU's three and
+/// the expiry index.
+const MAX_KEY_COPIES: u64 = 5;
+
+/// Memory charged against the limit for an item whose key is held `key_copies` times; allocator
+/// and hash table slack is not included.
+const fn charge_for(key_copies: u64, key_length: u64, value_length: u64) -> u64 {
+    key_copies * (key_length + KEY_COPY_OVERHEAD) + value_length + ITEM_OVERHEAD
+}
 
 struct McdValue {
-    value: String,
+    value: Bytes,
     flags: u32,
-    expire: Duration,
+    expire: Expiration,
+    cas: u64,
+    stored_at: u64,
+    accessed_at: AtomicU64,
+    /// Read at least once since it was stored.
+    fetched: AtomicBool,
+    /// Invalidated by `md I` / `ms I`; served until recached.
+    stale: bool,
+    /// The recache win for a stale item has been handed out.
+    win_sent: bool,
+    /// Slab class holding the item; assigned when it is linked.
+    class: usize,
+    /// Flush generation the item was stored in.
+    generation: u64,
 }
 
 impl McdValue {
-    fn new(value: String, options: WriteOptions) -> Self {
+    fn new(value: Bytes, options: WriteOptions, cas: u64, snapshot: &Snapshot) -> Self {
+        let now = snapshot.now;
         Self {
             value,
             flags: options.flags,
-            expire: options.expire,
+            expire: Expiration::from_exptime(options.expire, now),
+            cas,
+            stored_at: now,
+            accessed_at: AtomicU64::new(now),
+            fetched: AtomicBool::new(false),
+            stale: false,
+            win_sent: false,
+            class: 0,
+            generation: snapshot.generation(),
         }
     }
 
-    fn to_options(&self) -> WriteOptions {
-        WriteOptions {
+    fn access(&self, now: u64) {
+        self.accessed_at.store(now, Ordering::Relaxed);
+        self.fetched.store(true, Ordering::Relaxed);
+    }
+
+    fn to_value(&self, key: String) -> MemcachedValue {
+        MemcachedValue {
+            key,
             flags: self.flags,
-            expire: self.expire,
+            value: self.value.clone(),
+            cas: Some(self.cas),
+        }
+    }
+
+    fn to_item(&self, key: String, now: u64) -> MemcachedItem {
+        MemcachedItem {
+            value: self.to_value(key),
+            ttl: match self.expire {
+                Expiration::Never => None,
+                Expiration::At(deadline) => Some(deadline.saturating_sub(now)),
+            },
+            last_access: now.saturating_sub(self.accessed_at.load(Ordering::Relaxed)),
+            stale: self.stale,
+            win: false,
+            claimed: false,
+        }
+    }
+
+    fn size(&self, key: &str) -> u64 {
+        (key.len() + self.value.len()) as u64
+    }
+
+    fn is_live(&self, snapshot: &Snapshot) -> bool {
+        !self.expire.is_expired(snapshot.now) && self.generation >= snapshot.valid_from()
+    }
+}
+
+/// Time and flush state sampled once per operation.
+///
+/// Every `flush_all` starts two flush generations, for the items stored before and after its
+/// deadline; an immediate flush has no items before its deadline. Once a flush takes effect, the
+/// generations below the last one it started are invalid.
+struct Snapshot {
+    now: u64,
+    /// `flush_all` commands so far.
+    flushes: u64,
+    /// Deadline of the last `flush_all` if it had a delay, or 0.
+    flush_at: u64,
+    /// Generations invalidated by the flushes before the last.
+    flushed_below: u64,
+}
+
+impl Snapshot {
+    /// Whether the last flush had a delay that has passed.
+    fn flush_due(&self) -> bool {
+        self.flush_at != 0 && self.flush_at <= self.now
+    }
+
+    /// The generation of an item stored now.
+    fn generation(&self) -> u64 {
+        2 * self.flushes + u64::from(self.flush_due())
+    }
+
+    /// The oldest generation whose items are still valid.
+    fn valid_from(&self) -> u64 {
+        if self.flush_due() {
+            self.flushed_below.max(2 * self.flushes + 1)
+        } else {
+            self.flushed_below
+        }
+    }
+}
+
+fn parse_counter(value: &[u8]) -> Result<u64, MemcachedError> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|v| u64::from_str(v.trim_end()).ok())
+        .ok_or(MemcachedError::FailedToParseInteger)
+}
+
+fn out_of_memory() -> MemcachedError {
+    MemcachedError::Server("out of memory storing object".to_string())
+}
+
+fn too_large() -> MemcachedError {
+    MemcachedError::Server("object too large for cache".to_string())
+}
+
+/// Storage options that cannot work together, reported when the storage is built.
+#[derive(Debug, Eq, PartialEq)]
+pub struct InvalidOptions(pub String);
+
+impl std::fmt::Display for InvalidOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid storage options: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidOptions {}
+
+#[derive(Clone, Debug)]
+pub struct StorageOptions {
+    /// How often the reaper wakes up to reclaim expired and flushed items; must not be zero.
+    pub reaper_interval: Duration,
+    /// Most items reclaimed per write lock acquisition; must not be zero.
+    pub reaper_batch_size: usize,
+    /// Memory limit, handed out to slab classes one page at a time. It covers every copy of the
+    /// keys, the values, the per-item bookkeeping and the eviction policies' fixed-size state;
+    /// allocator and hash table slack is not counted.
+    pub max_bytes: u64,
+    /// Smallest key and value size a slab class is made for; its chunks add the per-item
+    /// overhead.
+    pub slab_chunk_size: u64,
+    /// Each slab class has chunks this many times larger than the previous one; must be greater
+    /// than one.
+    pub slab_growth_factor: f64,
+    /// Memory moved between slab classes at once; also the largest item, overhead included. The
+    /// default fits the largest key and item the server accepts by default.
+    pub slab_page_size: u64,
+    /// How often pages are moved towards classes that have to evict.
+    pub slab_automove_interval: Duration,
+    /// Evict items picked by `eviction_policy` when full; otherwise refuse the write.
+    pub evictions: bool,
+    /// Number of independently locked partitions the keys are spread over.
+    pub shards: usize,
+    /// Chooses what to evict; every slab class of every shard gets its own instance.
+    pub eviction_policy: EvictionPolicyFactory,
+}
+
+impl Default for StorageOptions {
+    fn default() -> Self {
+        let server = ServerOptions::default();
+        Self {
+            reaper_interval: Duration::from_secs(1),
+            reaper_batch_size: 100,
+            max_bytes: 64 * 1024 * 1024,
+            slab_chunk_size: 48,
+            slab_growth_factor: 1.25,
+            slab_page_size: charge_for(
+                MAX_KEY_COPIES,
+                server.max_key_length as u64,
+                server.max_item_size as u64,
+            ),
+            slab_automove_interval: Duration::from_secs(10),
+            evictions: true,
+            shards: 16,
+            eviction_policy: || Box::new(Lru::default()),
+        }
+    }
+}
+
+impl StorageOptions {
+    fn validate(&self) -> Result<(), InvalidOptions> {
+        let invalid = |message: &str| Err(InvalidOptions(message.to_string()));
+        if self.reaper_interval.is_zero() {
+            return invalid("reaper_interval must not be zero");
+        }
+        if self.reaper_batch_size == 0 {
+            return invalid("reaper_batch_size must not be zero");
         }
+        if self.slab_chunk_size == 0 {
+            return invalid("slab_chunk_size must not be zero");
+        }
+        if self.slab_page_size == 0 {
+            return invalid("slab_page_size must not be zero");
+        }
+        if self.slab_growth_factor.is_nan() || self.slab_growth_factor <= 1.0 {
+            return invalid("slab_growth_factor must be greater than 1");
+        }
+        Ok(())
+    }
+}
+
+/// One eviction policy per slab class, created when the class gets its first item.
+struct ClassPolicies {
+    policies: Vec<Option<Box<dyn EvictionPolicy>>>,
+    factory: EvictionPolicyFactory,
+}
+
+impl ClassPolicies {
+    /// Creates the policy of `class` if it does not exist yet and returns the fixed memory it
+    /// newly takes.
+    fn create(&mut self, class: usize) -> u64 {
+        let created = !matches!(self.policies.get(class), Some(Some(_)));
+        let policy = self.get(class);
+        if created {
+            policy.footprint()
+        } else {
+            0
+        }
+    }
+
+    fn get(&mut self, class: usize) -> &mut dyn EvictionPolicy {
+        if self.policies.len() <= class {
+            self.policies.resize_with(class + 1, || None);
+        }
+        self.policies[class]
+            .get_or_insert_with(self.factory)
+            .as_mut()
     }
 }
 
+/// Items and bytes of a shard stored in one flush generation.
 #[derive(Default)]
-pub struct HashMapStorage {
+struct Gauge {
+    items: u64,
+    bytes: u64,
+}
+
+/// One independently locked partition of the keyspace.
+struct Shard {
     hash_map: RwLock<HashMap<String, McdValue>>,
+    /// Only changed while holding the `hash_map` write lock.
+    expiry: Mutex<ExpiryIndex>,
+    /// Told about reads under the read lock too; keys are only added or removed under the write
+    /// lock.
+    policies: Mutex<ClassPolicies>,
+    /// What `curr_items` and `bytes` count, by flush generation; only changed while holding the
+    /// `hash_map` write lock.
+    generations: Mutex<BTreeMap<u64, Gauge>>,
+}
+
+impl Shard {
+    fn new(factory: EvictionPolicyFactory) -> Self {
+        Self {
+            hash_map: RwLock::default(),
+            expiry: Mutex::default(),
+            policies: Mutex::new(ClassPolicies {
+                policies: vec![],
+                factory,
+            }),
+            generations: Mutex::default(),
+        }
+    }
+
+    fn expiry(&self) -> MutexGuard<'_, ExpiryIndex> {
+        self.expiry
+            .lock()
+            .expect("Expiry index lock is not poisoned")
+    }
+
+    fn policies(&self) -> MutexGuard<'_, ClassPolicies> {
+        self.policies
+            .lock()
+            .expect("Eviction policy lock is not poisoned")
+    }
+
+    fn generations(&self) -> MutexGuard<'_, BTreeMap<u64, Gauge>> {
+        self.generations
+            .lock()
+            .expect("Generation lock is not poisoned")
+    }
+
+    /// Counts an item towards `curr_items` and `bytes`.
+    fn count(&self, key: &str, item: &McdValue) {
+        let mut generations = self.generations();
+        let gauge = generations.entry(item.generation).or_default();
+        gauge.items += 1;
+        gauge.bytes += item.size(key);
+    }
+
+    /// Stops counting an item towards `curr_items` and `bytes`.
+    fn uncount(&self, key: &str, item: &McdValue) {
+        let mut generations = self.generations();
+        let gauge = generations
+            .get_mut(&item.generation)
+            .expect("Item is counted");
+        gauge.items -= 1;
+        gauge.bytes -= item.size(key);
+        if gauge.items == 0 {
+            generations.remove(&item.generation);
+        }
+    }
+}
+
+pub struct HashMapStorage {
+    shards: Vec<Shard>,
+    /// Only locked briefly, possibly while holding a shard lock.
+    slabs: Mutex<Slabs>,
+    hasher: RandomState,
+    cas_counter: AtomicU64,
+    /// Flush state; see `Snapshot`.
+    flushes: AtomicU64,
+    flush_at: AtomicU64,
+    flushed_below: AtomicU64,
+    /// The oldest valid generation as of the last sweep of flushed items.
+    swept_below: AtomicU64,
+    clock: Arc<dyn Clock>,
+    statistics: StorageStatistics,
+    options: StorageOptions,
+    /// Name of the policy `options.eviction_policy` builds, for `stats settings`.
+    eviction_policy_name: &'static str,
+    /// Copies of a key the policy `options.eviction_policy` builds holds.
+    policy_key_copies: u64,
+}
+
+impl Default for HashMapStorage {
+    fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl HashMapStorage {
+    pub fn new(options: StorageOptions, clock: Arc<dyn Clock>) -> Result<Self, InvalidOptions> {
+        options.validate()?;
+        let statistics = StorageStatistics::default();
+        statistics
+            .limit_maxbytes
+            .store(options.max_bytes, Ordering::Relaxed);
+        let policy = (options.eviction_policy)();
+        let (eviction_policy_name, policy_key_copies) = (policy.name(), policy.key_copies());
+        Ok(Self {
+            shards: (0..options.shards.max(1))
+                .map(|_| Shard::new(options.eviction_policy))
+                .collect(),
+            slabs: Mutex::new(Slabs::new(
+                options.max_bytes,
+                options.slab_page_size,
+                options.slab_chunk_size + charge_for(1 + policy_key_copies, 0, 0),
+                options.slab_growth_factor,
+            )),
+            hasher: RandomState::new(),
+            cas_counter: AtomicU64::default(),
+            flushes: AtomicU64::default(),
+            flush_at: AtomicU64::default(),
+            flushed_below: AtomicU64::default(),
+            swept_below: AtomicU64::default(),
+            clock,
+            statistics,
+            options,
+            eviction_policy_name,
+            policy_key_copies,
+        })
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self::new(StorageOptions::default(), clock).expect("Default options are valid")
+    }
+
+    /// Spawns the task that reclaims expired and flushed items; it stops once the storage is
+    /// dropped.
+    pub fn start_reaper(self: &Arc<Self>) -> JoinHandle<()> {
+        let storage = Arc::downgrade(self);
+        let mut ticker = tokio::time::interval(self.options.reaper_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        tokio::spawn(async move {
+            loop {
+                ticker.tick().await;
+                let Some(storage) = storage.upgrade() else {
+                    break;
+                };
+                storage.reap().await;
+            }
+        })
+    }
+
+    /// Reclaims every expired item and every item of a flush that took effect, one batch per
+    /// lock acquisition, and returns how many were removed.
+    async fn reap(&self) -> usize {
+        let mut reclaimed = self.sweep_flushed().await;
+        for shard in &self.shards {
+            loop {
+                let count = self.reap_batch(shard).await;
+                reclaimed += count;
+                if count < self.options.reaper_batch_size {
+                    break;
+                }
+                // More may be left; let other tasks take the lock in between.
+                tokio::task::yield_now().await;
+            }
+        }
+        reclaimed
+    }
+
+    async fn reap_batch(&self, shard: &Shard) -> usize {
+        let now = self.clock.now();
+        let mut hm = shard.hash_map.write().await;
+        self.reclaim_expired(shard, &mut hm, "", now, self.options.reaper_batch_size)
+            .len()
+    }
+
+    /// Unlinks up to `limit` expired items of the locked shard other than `except` and returns
+    /// them.
+    fn reclaim_expired(
+        &self,
+        shard: &Shard,
+        hm: &mut HashMap<String, McdValue>,
+        except: &str,
+        now: u64,
+        limit: usize,
+    ) -> Vec<McdValue> {
+        let keys = shard.expiry().pop_expired(now, limit);
+        let mut reclaimed = Vec::with_capacity(keys.len());
+        for key in &keys {
+            if key == except {
+                // The caller is working on the item; leave it to the reaper.
+                if let Some(value) = hm.get(key.as_str()) {
+                    shard.expiry().insert(key, value.expire);
+                }
+                continue;
+            }
+            let Some(value) = self.unlink(shard, hm, key) else {
+                continue;
+            };
+            bump(&self.statistics.reclaimed);
+            if !value.fetched.load(Ordering::Relaxed) {
+                bump(&self.statistics.expired_unfetched);
+            }
+            reclaimed.push(value);
+        }
+        reclaimed
+    }
+
+    /// Whether a flush took effect whose items `sweep_flushed` has not removed yet.
+    fn flush_unswept(&self, snapshot: &Snapshot) -> bool {
+        snapshot.valid_from() > self.swept_below.load(Ordering::Relaxed)
+    }
+
+    /// Unlinks the items invalidated by a `flush_all` that took effect since the last sweep, so
+    /// that their chunks can be reused. Returns how many were removed.
+    async fn sweep_flushed(&self) -> usize {
+        let snapshot = self.snapshot();
+        if !self.flush_unswept(&snapshot) {
+            return 0;
+        }
+
+        let mut swept = 0;
+        for shard in &self.shards {
+            let flushed = shard.generations().first_key_value().map(|(g, _)| *g);
+            if flushed.is_none_or(|generation| generation >= snapshot.valid_from()) {
+                continue;
+            }
+            let dead: Vec<String> = {
+                let hm = shard.hash_map.read().await;
+                hm.iter()
+                    .filter(|(_, v)| !v.is_live(&snapshot))
+                    .map(|(k, _)| k.clone())
+                    .collect()
+            };
+            for batch in dead.chunks(self.options.reaper_batch_size) {
+                let mut hm = shard.hash_map.write().await;
+                for key in batch {
+                    // The key may have been stored again since the scan.
+                    if hm.get(key.as_str()).is_some_and(|v| !v.is_live(&snapshot)) {
+                        self.unlink(shard, &mut hm, key);
+                        bump(&self.statistics.reclaimed);
+                        swept += 1;
+                    }
+                }
+                drop(hm);
+                tokio::task::yield_now().await;
+            }
+        }
+        self.swept_below
+            .fetch_max(snapshot.valid_from(), Ordering::Relaxed);
+        swept
+    }
+
+    /// Spawns the task that moves slab pages towards the classes that evict; it stops once the
+    /// storage is dropped.
+    pub fn start_slab_automove(self: &Arc<Self>) -> JoinHandle<()> {
+        let storage = Arc::downgrade(self);
+        let mut ticker = tokio::time::interval(self.options.slab_automove_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        tokio::spawn(async move {
+            loop {
+                ticker.tick().await;
+                let Some(storage) = storage.upgrade() else {
+                    break;
+                };
+                storage.automove().await;
+            }
+        })
+    }
+
+    /// Moves at most one page, evicting items of the source class if its free chunks do not
+    /// add up to a page. Returns whether a page was moved.
+    async fn automove(&self) -> bool {
+        let Some((source, destination)) = self.slabs().plan_move() else {
+            return false;
+        };
+        loop {
+            let missing = {
+                let mut slabs = self.slabs();
+                if slabs.move_page(source, destination) {
+                    bump(&self.statistics.slabs_moved);
+                    return true;
+                }
+                slabs.missing_for_page(source)
+            };
+            // Spread the evictions over the shards, one lock acquisition each.
+            let per_shard = missing.div_ceil(self.shards.len() as u64);
+            let mut evicted = 0;
+            for shard in &self.shards {
+                let mut hm = shard.hash_map.write().await;
+                for _ in 0..per_shard.min(missing - evicted) {
+                    let victim = shard.policies().get(source).victim("");
+                    let Some(victim) = victim else {
+                        break;
+                    };
+                    self.unlink(shard, &mut hm, &victim);
+                    bump(&self.statistics.slab_reassign_evictions_nomem);
+                    evicted += 1;
+                }
+            }
+            if evicted == 0 {
+                return false;
+            }
+        }
+    }
+
+    fn slabs(&self) -> MutexGuard<'_, Slabs> {
+        self.slabs.lock().expect("Slab lock is not poisoned")
+    }
+
+    fn shard(&self, key: &str) -> &Shard {
+        &self.shards[self.shard_index(key)]
+    }
+
+    fn shard_index(&self, key: &str) -> usize {
+        (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    /// Positions of `keys` grouped by shard, so that each shard is locked only once.
+    fn group_by_shard(&self, keys: &[String]) -> Vec<(&Shard, Vec<usize>)> {
+        let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (position, key) in keys.iter().enumerate() {
+            groups
+                .entry(self.shard_index(key))
+                .or_default()
+                .push(position);
+        }
+        groups
+            .into_iter()
+            .map(|(index, positions)| (&self.shards[index], positions))
+            .collect()
+    }
+
+    /// Calls `f` with every live item, holding one shard's read lock at a time.
+    async fn for_each_live(&self, snapshot: &Snapshot, mut f: impl FnMut(&str, &McdValue)) {
+        for shard in &self.shards {
+            let hm = shard.hash_map.read().await;
+            for (key, value) in hm.iter().filter(|(_, v)| v.is_live(snapshot)) {
+                f(key, value);
+            }
+        }
+    }
+
+    /// Records a read with the item and the eviction policy.
+    fn access(&self, shard: &Shard, key: &str, item: &McdValue, now: u64) {
+        item.access(now);
+        shard.policies().get(item.class).access(key);
+    }
+
+    /// The chunk size an item needs. The expiry index only holds a copy of the key while the item
+    /// has a deadline; a deadline added by a touch is charged once the item is stored again.
+    fn charge(&self, key: &str, value_length: usize, expire: Expiration) -> u64 {
+        let key_copies = 1 + self.policy_key_copies + u64::from(expire != Expiration::Never);
+        charge_for(key_copies, key.len() as u64, value_length as u64)
+    }
+
+    /// The slab class for an item of `charge` bytes.
+    fn class_for(&self, charge: u64) -> Result<usize, MemcachedError> {
+        self.slabs().class_for(charge).ok_or_else(too_large)
+    }
+
+    /// Takes a chunk of `class` for `key`, evicting other items of the class while it is full.
+    /// Without evictions, only items that were dead at `now` are given up.
+    fn allocate(
+        &self,
+        shard: &Shard,
+        hm: &mut HashMap<String, McdValue>,
+        key: &str,
+        class: usize,
+        now: u64,
+    ) -> Result<(), MemcachedError> {
+        let footprint = shard.policies().create(class);
+        if footprint > 0 {
+            self.slabs().reserve(footprint);
+        }
+        while !self.slabs().allocate(class) {
+            if !self.options.evictions {
+                if !self.reclaim_dead(shard, hm, key, class, now) {
+                    return Err(out_of_memory());
+                }
+                continue;
+            }
+            if !self.evict(shard, hm, key, class) {
+                return Err(out_of_memory());
+            }
+            self.slabs().record_eviction(class);
+            bump(&self.statistics.evictions);
+        }
+        Ok(())
+    }
+
+    /// Unlinks the items other than `key` the reaper has not reclaimed yet, expired or flushed,
+    /// looking in the locked shard first. Other shards are skipped while they are busy. Returns
+    /// whether a chunk of `class` was freed.
+    fn reclaim_dead(
+        &self,
+        shard: &Shard,
+        hm: &mut HashMap<String, McdValue>,
+        key: &str,
+        class: usize,
+        now: u64,
+    ) -> bool {
+        // The caller checked liveness at `now`; a later time could reclaim the item it holds.
+        let snapshot = Snapshot {
+            now,
+            ..self.snapshot()
+        };
+        let flush_unswept = self.flush_unswept(&snapshot);
+        let reclaim = |shard: &Shard, hm: &mut HashMap<String, McdValue>| {
+            let limit = self.options.reaper_batch_size;
+            let mut freed = self
+                .reclaim_expired(shard, hm, key, snapshot.now, limit)
+                .iter()
+                .any(|value| value.class == class);
+            if flush_unswept {
+                // Only a full scan finds flushed items; it is left to the reaper otherwise.
+                let dead: Vec<String> = hm
+                    .iter()
+                    .filter(|(k, v)| v.class == class && *k != key && !v.is_live(&snapshot))
+                    .map(|(k, _)| k.clone())
+                    .take(limit)
+                    .collect();
+                for dead_key in &dead {
+                    self.unlink(shard, hm, dead_key);
+                    bump(&self.statistics.reclaimed);
+                }
+                freed |= !dead.is_empty();
+            }
+            freed
+        };
+        reclaim(shard, hm)
+            || self
+                .shards
+                .iter()
+                .filter(|other| !std::ptr::eq(*other, shard))
+                .any(|other| {
+                    let Ok(mut hm) = other.hash_map.try_write() else {
+                        return false;
+                    };
+                    reclaim(other, &mut hm)
+                })
+    }
+
+    /// Evicts the policy's victim of `class` other than `key`, looking in the locked shard first.
+    /// Other shards are skipped while they are busy.
+    fn evict(
+        &self,
+        shard: &Shard,
+        hm: &mut HashMap<String, McdValue>,
+        key: &str,
+        class: usize,
+    ) -> bool {
+        let victim = shard.policies().get(class).victim(key);
+        if let Some(victim) = victim {
+            self.unlink(shard, hm, &victim);
+            return true;
+        }
+        self.shards
+            .iter()
+            .filter(|other| !std::ptr::eq(*other, shard))
+            .any(|other| {
+                let Ok(mut hm) = other.hash_map.try_write() else {
+                    return false;
+                };
+                let victim = other.policies().get(class).victim(key);
+                victim
+                    .and_then(|victim| self.unlink(other, &mut hm, &victim))
+                    .is_some()
+            })
+    }
+
+    fn next_cas(&self) -> u64 {
+        self.cas_counter.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            now: self.clock.now(),
+            // A flush stores its deadline before counting itself.
+            flushes: self.flushes.load(Ordering::Acquire),
+            flush_at: self.flush_at.load(Ordering::Acquire),
+            flushed_below: self.flushed_below.load(Ordering::Acquire),
+        }
+    }
+
+    /// The `stats` counters, with `curr_items` and `bytes` summed over the valid flush
+    /// generations rather than the items.
+    fn report(&self) -> Vec<(String, String)> {
+        let valid_from = self.snapshot().valid_from();
+        let mut live = Gauge::default();
+        for shard in &self.shards {
+            for gauge in shard
+                .generations()
+                .range(valid_from..)
+                .map(|(_, gauge)| gauge)
+            {
+                live.items += gauge.items;
+                live.bytes += gauge.bytes;
+            }
+        }
+        let mut report = self.statistics.report();
+        report.push(("bytes".to_string(), live.bytes.to_string()));
+        report.push(("curr_items".to_string(), live.items.to_string()));
+        report
+    }
+
+    fn link(
+        &self,
+        shard: &Shard,
+        hm: &mut HashMap<String, McdValue>,
+        key: String,
+        mut value: McdValue,
+    ) -> Result<(), MemcachedError> {
+        value.class = self.class_for(self.charge(&key, value.value.len(), value.expire))?;
+        let old_class = hm.get(key.as_str()).map(|old| old.class);
+        if old_class != Some(value.class) {
+            self.allocate(shard, hm, &key, value.class, value.stored_at)?;
+        }
+
+        shard.count(&key, &value);
+        let mut expiry = shard.expiry();
+        let mut policies = shard.policies();
+        match hm.get(key.as_str()) {
+            Some(old) => {
+                shard.uncount(&key, old);
+                expiry.remove(&key, old.expire);
+                if old.class == value.class {
+                    policies.get(value.class).access(&key);
+                } else {
+                    policies.get(old.class).remove(&key);
+                    policies.get(value.class).insert(&key);
+                    self.slabs().release(old.class);
+                }
+            }
+            None => {
+                policies.get(value.class).insert(&key);
+            }
+        }
+        expiry.insert(&key, value.expire);
+        hm.insert(key, value);
+        bump(&self.statistics.total_items);
+        Ok(())
+    }
+
+    fn unlink(
+        &self,
+        shard: &Shard,
+        hm: &mut HashMap<String, McdValue>,
+        key: &str,
+    ) -> Option<McdValue> {
+        let value = hm.remove(key)?;
+        shard.expiry().remove(key, value.expire);
+        shard.policies().get(value.class).remove(key);
+        self.slabs().release(value.class);
+        shard.uncount(key, &value);
+        Some(value)
+    }
+
+    fn set_expire(&self, shard: &Shard, key: &str, item: &mut McdValue, expire: Expiration) {
+        let mut expiry = shard.expiry();
+        expiry.remove(key, item.expire);
+        expiry.insert(key, expire);
+        item.expire = expire;
+    }
+
+    /// Replaces an item's data in place, keeping its flags and deadline.
+    fn modify<'a>(
+        &self,
+        shard: &Shard,
+        hm: &'a mut HashMap<String, McdValue>,
+        key: &str,
+        value: Bytes,
+        snapshot: &Snapshot,
+    ) -> Result<&'a mut McdValue, MemcachedError> {
+        let item = hm.get(key).expect("Modified item exists");
+        let old_class = item.class;
+        let class = self.class_for(self.charge(key, value.len(), item.expire))?;
+        if class != old_class {
+            self.allocate(shard, hm, key, class, snapshot.now)?;
+            let mut policies = shard.policies();
+            policies.get(old_class).remove(key);
+            policies.get(class).insert(key);
+            self.slabs().release(old_class);
+        }
+
+        let item = hm.get_mut(key).expect("Modified item exists");
+        shard.uncount(key, item);
+        item.value = value;
+        item.class = class;
+        item.cas = self.next_cas();
+        item.stored_at = snapshot.now;
+        item.generation = snapshot.generation();
+        shard.count(key, item);
+        bump(&self.statistics.total_items);
+        Ok(item)
+    }
 }
 
 #[async_trait]
 impl MemcachedHandler for HashMapStorage {
-    async fn set(&self, key: String, value: String, options: WriteOptions) -> MemcachedResult {
-        let mut hm = self.hash_map.write().await;
-        hm.insert(key, McdValue::new(value, options));
+    async fn set(&self, key: String, value: Bytes, options: WriteOptions) -> MemcachedResult {
+        bump(&self.statistics.cmd_set);
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        self.link(
+            shard,
+            &mut hm,
+            key,
+            McdValue::new(value, options, self.next_cas(), &snapshot),
+        )?;
         Ok(MemcachedResponse::Stored)
     }
 
-    async fn add(&self, key: String, value: String, options: WriteOptions) -> MemcachedResult {
-        let mut hm = self.hash_map.write().await;
-        if hm.contains_key(key.as_str()) {
-            return Err(MemcachedError::AlreadyExists);
+    async fn add(&self, key: String, value: Bytes, options: WriteOptions) -> MemcachedResult {
+        bump(&self.statistics.cmd_set);
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        if hm.get(key.as_str()).is_some_and(|v| v.is_live(&snapshot)) {
+            return Err(MemcachedError::NotStored);
         }
-        hm.insert(key, McdValue::new(value, options));
+        self.link(
+            shard,
+            &mut hm,
+            key,
+            McdValue::new(value, options, self.next_cas(), &snapshot),
+        )?;
         Ok(MemcachedResponse::Stored)
     }
 
-    async fn replace(&self, key: String, value: String, options: WriteOptions) -> MemcachedResult {
-        let mut hm = self.hash_map.write().await;
-        if !hm.contains_key(key.as_str()) {
-            return Err(MemcachedError::NotFound);
+    async fn replace(&self, key: String, value: Bytes, options: WriteOptions) -> MemcachedResult {
+        bump(&self.statistics.cmd_set);
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        if !hm.get(key.as_str()).is_some_and(|v| v.is_live(&snapshot)) {
+            return Err(MemcachedError::NotStored);
         }
-        hm.insert(key, McdValue::new(value, options));
+        self.link(
+            shard,
+            &mut hm,
+            key,
+            McdValue::new(value, options, self.next_cas(), &snapshot),
+        )?;
         Ok(MemcachedResponse::Stored)
     }
 
-    async fn append(&self, key: String, value: String, options: WriteOptions) -> MemcachedResult {
-        let mut hm = self.hash_map.write().await;
-        let Some(old_value) = hm.get(key.as_str()) else {
-            return Err(MemcachedError::NotFound);
+    async fn append(&self, key: String, value: Bytes, _options: WriteOptions) -> MemcachedResult {
+        bump(&self.statistics.cmd_set);
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        let Some(old_value) = hm.get(key.as_str()).filter(|v| v.is_live(&snapshot)) else {
+            return Err(MemcachedError::NotStored);
         };
-        let new_value = format!("{}{value}", old_value.value);
+        let mut new_value = BytesMut::with_capacity(old_value.value.len() + value.len());
+        new_value.put_slice(&old_value.value);
+        new_value.put_slice(&value);
 
-        hm.insert(key, McdValue::new(new_value, options));
+        self.modify(shard, &mut hm, &key, new_value.freeze(), &snapshot)?;
         Ok(MemcachedResponse::Stored)
     }
 
-    async fn prepend(&self, key: String, value: String, options: WriteOptions) -> MemcachedResult {
-        let mut hm = self.hash_map.write().await;
-        let Some(old_value) = hm.get(key.as_str()) else {
-            return Err(MemcachedError::NotFound);
+    async fn prepend(&self, key: String, value: Bytes, _options: WriteOptions) -> MemcachedResult {
+        bump(&self.statistics.cmd_set);
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        let Some(old_value) = hm.get(key.as_str()).filter(|v| v.is_live(&snapshot)) else {
+            return Err(MemcachedError::NotStored);
         };
-        let new_value = format!("{value}{}", old_value.value);
+        let mut new_value = BytesMut::with_capacity(old_value.value.len() + value.len());
+        new_value.put_slice(&value);
+        new_value.put_slice(&old_value.value);
+
+        self.modify(shard, &mut hm, &key, new_value.freeze(), &snapshot)?;
+        Ok(MemcachedResponse::Stored)
+    }
 
-        hm.insert(key, McdValue::new(new_value, options));
+    async fn cas(
+        &self,
+        key: String,
+        value: Bytes,
+        options: WriteOptions,
+        cas_unique: u64,
+    ) -> MemcachedResult {
+        bump(&self.statistics.cmd_set);
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        let Some(old_value) = hm.get(key.as_str()).filter(|v| v.is_live(&snapshot)) else {
+            bump(&self.statistics.cas_misses);
+            return Err(MemcachedError::NotFound);
+        };
+        if old_value.cas != cas_unique {
+            bump(&self.statistics.cas_badval);
+            return Err(MemcachedError::Exists);
+        }
+        bump(&self.statistics.cas_hits);
+        self.link(
+            shard,
+            &mut hm,
+            key,
+            McdValue::new(value, options, self.next_cas(), &snapshot),
+        )?;
         Ok(MemcachedResponse::Stored)
     }
 
     async fn get(&self, key: String) -> MemcachedResult {
-        let hm = self.hash_map.read().await;
-        match hm.get(key.as_str()) {
-            Some(value) => Ok(MemcachedResponse::Value {
-                key,
-                flags: value.flags,
-                expire: value.expire,
-                value: value.value.to_string(),
-            }),
+        bump(&self.statistics.cmd_get);
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let hm = shard.hash_map.read().await;
+        let value = hm.get(key.as_str()).filter(|v| v.is_live(&snapshot));
+        hit_or_miss(
+            value.is_some(),
+            &self.statistics.get_hits,
+            &self.statistics.get_misses,
+        );
+        match value {
+            Some(value) => {
+                self.access(shard, &key, value, snapshot.now);
+                Ok(MemcachedResponse::Value(value.to_value(key)))
+            }
             None => Err(MemcachedError::NotFound),
         }
     }
 
-    async fn delete(&self, key: String) -> MemcachedResult {
-        let mut hm = self.hash_map.write().await;
+    async fn get_multi(&self, keys: Vec<String>) -> MemcachedResult {
+        let snapshot = self.snapshot();
+        let mut values: Vec<Option<MemcachedValue>> = keys.iter().map(|_| None).collect();
+        for (shard, positions) in self.group_by_shard(&keys) {
+            let hm = shard.hash_map.read().await;
+            for position in positions {
+                let key = &keys[position];
+                bump(&self.statistics.cmd_get);
+                let value = hm.get(key.as_str()).filter(|v| v.is_live(&snapshot));
+                hit_or_miss(
+                    value.is_some(),
+                    &self.statistics.get_hits,
+                    &self.statistics.get_misses,
+                );
+                if let Some(value) = value {
+                    self.access(shard, key, value, snapshot.now);
+                    values[position] = Some(value.to_value(key.clone()));
+                }
+            }
+        }
+        Ok(MemcachedResponse::Values(
+            values.into_iter().flatten().collect(),
+        ))
+    }
 
-        if hm.remove(key.as_str()).is_none() {
+    async fn delete(&self, key: String) -> MemcachedResult {
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+
+        let live = self
+            .unlink(shard, &mut hm, key.as_str())
+            .is_some_and(|v| v.is_live(&snapshot));
+        hit_or_miss(
+            live,
+            &self.statistics.delete_hits,
+            &self.statistics.delete_misses,
+        );
+        if live {
+            Ok(MemcachedResponse::Deleted)
+        } else {
             Err(MemcachedError::NotFound)
+        }
+    }
+
+    async fn touch(&self, key: String, expire: i64) -> MemcachedResult {
+        bump(&self.statistics.cmd_touch);
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        let value = hm.get_mut(key.as_str()).filter(|v| v.is_live(&snapshot));
+        hit_or_miss(
+            value.is_some(),
+            &self.statistics.touch_hits,
+            &self.statistics.touch_misses,
+        );
+        let Some(value) = value else {
+            return Err(MemcachedError::NotFound);
+        };
+        self.set_expire(
+            shard,
+            &key,
+            value,
+            Expiration::from_exptime(expire, snapshot.now),
+        );
+        self.access(shard, &key, value, snapshot.now);
+        Ok(MemcachedResponse::Touched)
+    }
+
+    async fn get_and_touch(&self, keys: Vec<String>, expire: i64) -> MemcachedResult {
+        let snapshot = self.snapshot();
+        let expire = Expiration::from_exptime(expire, snapshot.now);
+        let mut values: Vec<Option<MemcachedValue>> = keys.iter().map(|_| None).collect();
+        for (shard, positions) in self.group_by_shard(&keys) {
+            let mut hm = shard.hash_map.write().await;
+            for position in positions {
+                let key = &keys[position];
+                bump(&self.statistics.cmd_get);
+                bump(&self.statistics.cmd_touch);
+                let value = hm.get_mut(key.as_str()).filter(|v| v.is_live(&snapshot));
+                hit_or_miss(
+                    value.is_some(),
+                    &self.statistics.get_hits,
+                    &self.statistics.get_misses,
+                );
+                hit_or_miss(
+                    value.is_some(),
+                    &self.statistics.touch_hits,
+                    &self.statistics.touch_misses,
+                );
+                if let Some(value) = value {
+                    self.set_expire(shard, key, value, expire);
+                    self.access(shard, key, value, snapshot.now);
+                    values[position] = Some(value.to_value(key.clone()));
+                }
+            }
+        }
+        Ok(MemcachedResponse::Values(
+            values.into_iter().flatten().collect(),
+        ))
+    }
+
+    async fn increment(&self, key: String, diff: u64) -> MemcachedResult {
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        let old_value = hm.get(key.as_str()).filter(|v| v.is_live(&snapshot));
+        hit_or_miss(
+            old_value.is_some(),
+            &self.statistics.incr_hits,
+            &self.statistics.incr_misses,
+        );
+        let Some(old_value) = old_value else {
+            return Err(MemcachedError::NotFound);
+        };
+
+        let current = parse_counter(&old_value.value)?;
+        let new = current.wrapping_add(diff);
+
+        self.modify(
+            shard,
+            &mut hm,
+            &key,
+            Bytes::from(new.to_string()),
+            &snapshot,
+        )?;
+        Ok(MemcachedResponse::Number(new))
+    }
+
+    async fn decrement(&self, key: String, diff: u64) -> MemcachedResult {
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        let old_value = hm.get(key.as_str()).filter(|v| v.is_live(&snapshot));
+        hit_or_miss(
+            old_value.is_some(),
+            &self.statistics.decr_hits,
+            &self.statistics.decr_misses,
+        );
+        let Some(old_value) = old_value else {
+            return Err(MemcachedError::NotFound);
+        };
+
+        let current = parse_counter(&old_value.value)?;
+        let new = current.saturating_sub(diff);
+
+        self.modify(
+            shard,
+            &mut hm,
+            &key,
+            Bytes::from(new.to_string()),
+            &snapshot,
+        )?;
+        Ok(MemcachedResponse::Number(new))
+    }
+
+    async fn flush(&self, delay: i64) -> MemcachedResult {
+        bump(&self.statistics.cmd_flush);
+        let snapshot = self.snapshot();
+        let flush_at = if delay <= 0 {
+            0
         } else {
-            Ok(MemcachedResponse::Deleted)
+            match Expiration::from_exptime(delay, snapshot.now) {
+                Expiration::At(flush_at) => flush_at,
+                Expiration::Never => 0,
+            }
+        };
+        // A previous flush that took effect stays in effect. Flushed items are left for the reaper
+        // to sweep.
+        self.flushed_below
+            .fetch_max(snapshot.valid_from(), Ordering::Release);
+        self.flush_at.store(flush_at, Ordering::Release);
+        let flushes = self.flushes.fetch_add(1, Ordering::AcqRel) + 1;
+        if flush_at == 0 {
+            self.flushed_below.fetch_max(2 * flushes, Ordering::Release);
         }
+        Ok(MemcachedResponse::Ok)
+    }
+
+    async fn statistics(&self) -> MemcachedResult {
+        let mut statistics = self.report();
+        statistics.push((
+            "hit_ratio".to_string(),
+            format!("{:.4}", self.statistics.hit_ratio()),
+        ));
+        Ok(MemcachedResponse::Statistics(statistics))
     }
 
-    async fn increment(&self, key: String, diff: i64) -> MemcachedResult {
-        let mut hm = self.hash_map.write().await;
-        let Some(old_value) = hm.get(key.as_str()) else {
+    async fn meta_get(&self, key: String, touch: Option<i64>) -> MemcachedResult {
+        bump(&self.statistics.cmd_get);
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        if touch.is_none() {
+            // Only touching or handing out the win changes the item.
+            let hm = shard.hash_map.read().await;
+            let value = hm.get(key.as_str()).filter(|v| v.is_live(&snapshot));
+            if value.is_none_or(|v| !v.stale || v.win_sent) {
+                hit_or_miss(
+                    value.is_some(),
+                    &self.statistics.get_hits,
+                    &self.statistics.get_misses,
+                );
+                let Some(value) = value else {
+                    return Err(MemcachedError::NotFound);
+                };
+                let mut item = value.to_item(key.clone(), snapshot.now);
+                item.claimed = value.stale;
+                self.access(shard, &key, value, snapshot.now);
+                return Ok(MemcachedResponse::Item(item));
+            }
+        }
+
+        let mut hm = shard.hash_map.write().await;
+        let value = hm.get_mut(key.as_str()).filter(|v| v.is_live(&snapshot));
+        hit_or_miss(
+            value.is_some(),
+            &self.statistics.get_hits,
+            &self.statistics.get_misses,
+        );
+        if touch.is_some() {
+            bump(&self.statistics.cmd_touch);
+            hit_or_miss(
+                value.is_some(),
+                &self.statistics.touch_hits,
+                &self.statistics.touch_misses,
+            );
+        }
+        let Some(value) = value else {
             return Err(MemcachedError::NotFound);
         };
+        if let Some(expire) = touch {
+            self.set_expire(
+                shard,
+                &key,
+                value,
+                Expiration::from_exptime(expire, snapshot.now),
+            );
+        }
+
+        let mut item = value.to_item(key.clone(), snapshot.now);
+        if value.stale {
+            item.win = !value.win_sent;
+            item.claimed = value.win_sent;
+            value.win_sent = true;
+        }
+        self.access(shard, &key, value, snapshot.now);
+        Ok(MemcachedResponse::Item(item))
+    }
 
-        let current = i64::from_str(old_value.value.as_str())
-            .map_err(|_| MemcachedError::FailedToParseInteger)?;
-        let new = current + diff;
+    async fn meta_set(
+        &self,
+        key: String,
+        value: Bytes,
+        options: WriteOptions,
+        meta: MetaSetOptions,
+    ) -> MemcachedResult {
+        bump(&self.statistics.cmd_set);
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        let current = hm.get(key.as_str()).filter(|v| v.is_live(&snapshot));
+
+        let mut stale = false;
+        if let Some(compare_cas) = meta.compare_cas {
+            let Some(current) = current else {
+                bump(&self.statistics.cas_misses);
+                return Err(MemcachedError::NotFound);
+            };
+            if meta.invalidate && compare_cas < current.cas {
+                stale = true;
+            } else if compare_cas != current.cas {
+                bump(&self.statistics.cas_badval);
+                return Err(MemcachedError::Exists);
+            }
+            bump(&self.statistics.cas_hits);
+        }
 
-        let new_value = McdValue::new(new.to_string(), old_value.to_options());
+        let exists = current.is_some();
+        match meta.mode {
+            MetaSetMode::Add if exists => return Err(MemcachedError::NotStored),
+            MetaSetMode::Append | MetaSetMode::Prepend | MetaSetMode::Replace if !exists => {
+                return Err(MemcachedError::NotStored)
+            }
+            MetaSetMode::Append | MetaSetMode::Prepend => {
+                let old_value = hm.get(key.as_str()).expect("Item is live");
+                let mut new_value = BytesMut::with_capacity(old_value.value.len() + value.len());
+                if meta.mode == MetaSetMode::Append {
+                    new_value.put_slice(&old_value.value);
+                    new_value.put_slice(&value);
+                } else {
+                    new_value.put_slice(&value);
+                    new_value.put_slice(&old_value.value);
+                }
+                let item = self.modify(shard, &mut hm, &key, new_value.freeze(), &snapshot)?;
+                item.stale = stale;
+                return Ok(MemcachedResponse::Item(item.to_item(key, snapshot.now)));
+            }
+            _ => {}
+        }
 
-        hm.insert(key, new_value);
-        Ok(MemcachedResponse::Stored)
+        let mut new_value = McdValue::new(value, options, self.next_cas(), &snapshot);
+        new_value.stale = stale;
+        let item = new_value.to_item(key.clone(), snapshot.now);
+        self.link(shard, &mut hm, key, new_value)?;
+        Ok(MemcachedResponse::Item(item))
     }
 
-    async fn decrement(&self, key: String, diff: i64) -> MemcachedResult {
-        let mut hm = self.hash_map.write().await;
-        let Some(old_value) = hm.get(key.as_str()) else {
+    async fn meta_delete(&self, key: String, meta: MetaDeleteOptions) -> MemcachedResult {
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        let value = hm.get_mut(key.as_str()).filter(|v| v.is_live(&snapshot));
+        hit_or_miss(
+            value.is_some(),
+            &self.statistics.delete_hits,
+            &self.statistics.delete_misses,
+        );
+        let Some(value) = value else {
             return Err(MemcachedError::NotFound);
         };
+        if meta.compare_cas.is_some_and(|cas| cas != value.cas) {
+            return Err(MemcachedError::Exists);
+        }
 
-        let current = i64::from_str(old_value.value.as_str())
-            .map_err(|_| MemcachedError::FailedToParseInteger)?;
-        let new = current - diff;
+        if meta.invalidate {
+            value.stale = true;
+            value.win_sent = false;
+            value.cas = self.next_cas();
+            if let Some(expire) = meta.expire {
+                self.set_expire(
+                    shard,
+                    &key,
+                    value,
+                    Expiration::from_exptime(expire, snapshot.now),
+                );
+            }
+        } else {
+            self.unlink(shard, &mut hm, key.as_str());
+        }
+        Ok(MemcachedResponse::Deleted)
+    }
 
-        let new_value = McdValue::new(new.to_string(), old_value.to_options());
+    async fn meta_arithmetic(&self, key: String, meta: MetaArithmeticOptions) -> MemcachedResult {
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let mut hm = shard.hash_map.write().await;
+        let (hits, misses) = match meta.mode {
+            MetaArithmeticMode::Increment => {
+                (&self.statistics.incr_hits, &self.statistics.incr_misses)
+            }
+            MetaArithmeticMode::Decrement => {
+                (&self.statistics.decr_hits, &self.statistics.decr_misses)
+            }
+        };
+        let old_value = hm.get(key.as_str()).filter(|v| v.is_live(&snapshot));
+        hit_or_miss(old_value.is_some(), hits, misses);
+
+        let Some(old_value) = old_value else {
+            let Some(expire) = meta.autovivify else {
+                return Err(MemcachedError::NotFound);
+            };
+            let options = WriteOptions { flags: 0, expire };
+            let value = Bytes::from(meta.initial.to_string());
+            let new_value = McdValue::new(value, options, self.next_cas(), &snapshot);
+            let item = new_value.to_item(key.clone(), snapshot.now);
+            self.link(shard, &mut hm, key, new_value)?;
+            return Ok(MemcachedResponse::Item(item));
+        };
+        if meta.compare_cas.is_some_and(|cas| cas != old_value.cas) {
+            return Err(MemcachedError::Exists);
+        }
 
-        hm.insert(key, new_value);
-        Ok(MemcachedResponse::Stored)
+        let current = parse_counter(&old_value.value)?;
+        let new = match meta.mode {
+            MetaArithmeticMode::Increment => current.wrapping_add(meta.delta),
+            MetaArithmeticMode::Decrement => current.saturating_sub(meta.delta),
+        };
+        let item = self.modify(
+            shard,
+            &mut hm,
+            &key,
+            Bytes::from(new.to_string()),
+            &snapshot,
+        )?;
+        Ok(MemcachedResponse::Item(item.to_item(key, snapshot.now)))
     }
 
-    async fn statistics(&self) -> MemcachedResult {
-        Ok(MemcachedResponse::Statistics(HashMap::new()))
+    async fn meta_debug(&self, key: String) -> MemcachedResult {
+        let snapshot = self.snapshot();
+        let shard = self.shard(&key);
+        let hm = shard.hash_map.read().await;
+        match hm.get(key.as_str()).filter(|v| v.is_live(&snapshot)) {
+            Some(value) => Ok(MemcachedResponse::Item(value.to_item(key, snapshot.now))),
+            None => Err(MemcachedError::NotFound),
+        }
+    }
+
+    async fn statistics_for(&self, subcommand: &str) -> MemcachedResult {
+        let snapshot = self.snapshot();
+        let stats = match subcommand {
+            "items" => {
+                // Number of live items and the oldest store time, per slab class.
+                let mut classes: BTreeMap<usize, (u64, u64)> = BTreeMap::new();
+                self.for_each_live(&snapshot, |_, v| {
+                    let (number, oldest) = classes.entry(v.class).or_insert((0, u64::MAX));
+                    *number += 1;
+                    *oldest = (*oldest).min(v.stored_at);
+                })
+                .await;
+                let slabs = self.slabs();
+                classes
+                    .into_iter()
+                    .flat_map(|(class, (number, oldest))| {
+                        let id = class + 1;
+                        [
+                            (format!("items:{id}:number"), number),
+                            (
+                                format!("items:{id}:age"),
+                                snapshot.now.saturating_sub(oldest),
+                            ),
+                            (
+                                format!("items:{id}:evicted"),
+                                slabs.classes()[class].evictions,
+                            ),
+                        ]
+                    })
+                    .map(|(name, value)| (name, value.to_string()))
+                    .collect()
+            }
+            "slabs" => {
+                let mut requested = BTreeMap::new();
+                self.for_each_live(&snapshot, |k, v| {
+                    *requested.entry(v.class).or_insert(0) +=
+                        self.charge(k, v.value.len(), v.expire);
+                })
+                .await;
+                self.slabs().report(&requested)
+            }
+            "sizes" => {
+                let mut sizes = BTreeMap::new();
+                self.for_each_live(&snapshot, |key, value| {
+                    let bucket = value.size(key).div_ceil(SIZES_BUCKET) * SIZES_BUCKET;
+                    *sizes.entry(bucket).or_insert(0u64) += 1;
+                })
+                .await;
+                sizes
+                    .into_iter()
+                    .map(|(size, count)| (size.to_string(), count.to_string()))
+                    .collect()
+            }
+            "settings" => vec![
+                (
+                    "maxbytes".to_string(),
+                    self.statistics
+                        .limit_maxbytes
+                        .load(Ordering::Relaxed)
+                        .to_string(),
+                ),
+                ("cas_enabled".to_string(), "yes".to_string()),
+                (
+                    "evictions".to_string(),
+                    if self.options.evictions { "on" } else { "off" }.to_string(),
+                ),
+                (
+                    "eviction_policy".to_string(),
+                    self.eviction_policy_name.to_string(),
+                ),
+                (
+                    "chunk_size".to_string(),
+                    self.options.slab_chunk_size.to_string(),
+                ),
+                (
+                    "growth_factor".to_string(),
+                    format!("{:.2}", self.options.slab_growth_factor),
+                ),
+                (
+                    "slab_page_size".to_string(),
+                    self.options.slab_page_size.to_string(),
+                ),
+            ],
+            _ => return Err(MemcachedError::NoExistenceCommand),
+        };
+        Ok(MemcachedResponse::Statistics(stats))
+    }
+
+    async fn reset_statistics(&self) -> MemcachedResult {
+        self.statistics.reset();
+        Ok(MemcachedResponse::Reset)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::policy::{Slru, WTinyLfu};
+    use endpoint::ManualClock;
 
     #[tokio::test]
     async fn test_get_if_absent() {
@@ -162,366 +1523,2064 @@ mod tests {
 
         let options = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: 0,
         };
         storage
-            .set("key".to_string(), "value".to_string(), options)
+            .set("key".to_string(), Bytes::from("value"), options)
             .await
             .expect("Can set");
         let result = storage.get("key".to_string()).await;
 
         assert_eq!(
             result,
-            Ok(MemcachedResponse::Value {
+            Ok(MemcachedResponse::Value(MemcachedValue {
                 key: "key".to_string(),
                 flags: 0,
-                expire: Duration::from_secs(0),
-                value: "value".to_string()
-            })
+                value: Bytes::from("value"),
+                cas: Some(1)
+            }))
         );
     }
 
-    // add
     #[tokio::test]
-    async fn test_add_if_absent() {
+    async fn test_get_multi_skips_absent_keys() {
         let storage = HashMapStorage::default();
 
         let options = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: 0,
         };
         storage
-            .add("key".to_string(), "value".to_string(), options)
+            .set("key1".to_string(), Bytes::from("value1"), options.clone())
             .await
-            .expect("Can add");
-    }
+            .expect("Can set");
+        storage
+            .set("key3".to_string(), Bytes::from("value3"), options)
+            .await
+            .expect("Can set");
 
-    #[tokio::test]
-    async fn test_add_if_exists() {
+        let result = storage
+            .get_multi(vec![
+                "key1".to_string(),
+                "key2".to_string(),
+                "key3".to_string(),
+            ])
+            .await;
+
+        assert_eq!(
+            result,
+            Ok(MemcachedResponse::Values(vec![
+                MemcachedValue {
+                    key: "key1".to_string(),
+                    flags: 0,
+                    value: Bytes::from("value1"),
+                    cas: Some(1)
+                },
+                MemcachedValue {
+                    key: "key3".to_string(),
+                    flags: 0,
+                    value: Bytes::from("value3"),
+                    cas: Some(2)
+                },
+            ]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_multi_if_all_absent() {
+        let storage = HashMapStorage::default();
+
+        let result = storage
+            .get_multi(vec!["key1".to_string(), "key2".to_string()])
+            .await;
+
+        assert_eq!(result, Ok(MemcachedResponse::Values(vec![])));
+    }
+
+    #[tokio::test]
+    async fn test_set_and_get_binary_value() {
         let storage = HashMapStorage::default();
 
         let options = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: 0,
         };
+        let value = Bytes::from_static(&[0x00, 0xff, b'\r', b'\n', 0xc3, 0x28]);
         storage
-            .set("key".to_string(), "value".to_string(), options.clone())
+            .set("key".to_string(), value.clone(), options)
             .await
             .expect("Can set");
+        let result = storage.get("key".to_string()).await;
+
+        assert_eq!(
+            result,
+            Ok(MemcachedResponse::Value(MemcachedValue {
+                key: "key".to_string(),
+                flags: 0,
+                value,
+                cas: Some(1)
+            }))
+        );
+    }
+
+    // cas
+    #[tokio::test]
+    async fn test_cas_if_absent() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
 
         let result = storage
-            .add("key".to_string(), "value2".to_string(), options)
+            .cas("key".to_string(), Bytes::from("value"), options, 1)
             .await;
-        assert_eq!(result, Err(MemcachedError::AlreadyExists));
+        assert_eq!(result, Err(MemcachedError::NotFound));
     }
 
-    // replace
     #[tokio::test]
-    async fn test_replace_if_absent() {
+    async fn test_cas_if_unique_matches() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+        let Ok(MemcachedResponse::Values(values)) =
+            storage.get_multi(vec!["key".to_string()]).await
+        else {
+            panic!("Can get");
+        };
+        let cas_unique = values[0].cas.expect("Has cas unique");
+
+        storage
+            .cas(
+                "key".to_string(),
+                Bytes::from("value2"),
+                options,
+                cas_unique,
+            )
+            .await
+            .expect("Can cas");
+
+        let result = storage.get_multi(vec!["key".to_string()]).await;
+        let Ok(MemcachedResponse::Values(values)) = result else {
+            panic!("Can get");
+        };
+        assert_eq!(values[0].value, "value2");
+        assert!(values[0].cas.expect("Has cas unique") > cas_unique);
+    }
+
+    #[tokio::test]
+    async fn test_cas_if_unique_differs() {
         let storage = HashMapStorage::default();
 
         let options = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+        let Ok(MemcachedResponse::Values(values)) =
+            storage.get_multi(vec!["key".to_string()]).await
+        else {
+            panic!("Can get");
         };
+        let cas_unique = values[0].cas.expect("Has cas unique");
+        storage
+            .set("key".to_string(), Bytes::from("value2"), options.clone())
+            .await
+            .expect("Can set");
 
         let result = storage
-            .replace("key".to_string(), "value".to_string(), options)
+            .cas(
+                "key".to_string(),
+                Bytes::from("value3"),
+                options,
+                cas_unique,
+            )
             .await;
+        assert_eq!(result, Err(MemcachedError::Exists));
+
+        let result = storage.get("key".to_string()).await;
+        assert_eq!(
+            result,
+            Ok(MemcachedResponse::Value(MemcachedValue {
+                key: "key".to_string(),
+                flags: 0,
+                value: Bytes::from("value2"),
+                cas: Some(2)
+            }))
+        );
+    }
+
+    // expire
+    const NOW: u64 = 1_700_000_000;
+
+    fn storage_with_clock() -> (HashMapStorage, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(NOW));
+        (HashMapStorage::with_clock(clock.clone()), clock)
+    }
+
+    #[tokio::test]
+    async fn test_relative_expire() {
+        let (storage, clock) = storage_with_clock();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 10,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options)
+            .await
+            .expect("Can set");
+
+        clock.advance(9);
+        assert!(storage.get("key".to_string()).await.is_ok());
+
+        clock.advance(1);
+        let result = storage.get("key".to_string()).await;
         assert_eq!(result, Err(MemcachedError::NotFound));
     }
 
     #[tokio::test]
-    async fn test_replace_if_exists() {
-        let storage = HashMapStorage::default();
+    async fn test_absolute_expire() {
+        let (storage, clock) = storage_with_clock();
 
         let options = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: (NOW + 100) as i64,
         };
         storage
-            .set("key".to_string(), "value".to_string(), options.clone())
+            .set("key".to_string(), Bytes::from("value"), options)
             .await
             .expect("Can set");
 
+        clock.set(NOW + 99);
+        assert!(storage.get("key".to_string()).await.is_ok());
+
+        clock.set(NOW + 100);
+        let result = storage.get_multi(vec!["key".to_string()]).await;
+        assert_eq!(result, Ok(MemcachedResponse::Values(vec![])));
+    }
+
+    #[tokio::test]
+    async fn test_never_expire() {
+        let (storage, clock) = storage_with_clock();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
         storage
-            .replace("key".to_string(), "value2".to_string(), options)
+            .set("key".to_string(), Bytes::from("value"), options)
             .await
-            .expect("Can replace");
+            .expect("Can set");
+
+        clock.advance(365 * 24 * 60 * 60);
+        assert!(storage.get("key".to_string()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_negative_expire() {
+        let (storage, _) = storage_with_clock();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: -1,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options)
+            .await
+            .expect("Can set");
+
+        let result = storage.get("key".to_string()).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_expired_item_is_absent_for_writes() {
+        let (storage, clock) = storage_with_clock();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 1,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("1"), options.clone())
+            .await
+            .expect("Can set");
+        clock.advance(1);
+
+        let result = storage
+            .replace("key".to_string(), Bytes::from("2"), options.clone())
+            .await;
+        assert_eq!(result, Err(MemcachedError::NotStored));
+        let result = storage
+            .append("key".to_string(), Bytes::from("2"), options.clone())
+            .await;
+        assert_eq!(result, Err(MemcachedError::NotStored));
+        let result = storage.increment("key".to_string(), 1).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+        let result = storage.delete("key".to_string()).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+
+        storage
+            .add("key".to_string(), Bytes::from("3"), options)
+            .await
+            .expect("Can add");
+    }
+
+    #[tokio::test]
+    async fn test_append_keeps_expire() {
+        let (storage, clock) = storage_with_clock();
+
+        storage
+            .set(
+                "key".to_string(),
+                Bytes::from("value"),
+                WriteOptions {
+                    flags: 0,
+                    expire: 10,
+                },
+            )
+            .await
+            .expect("Can set");
+        storage
+            .append(
+                "key".to_string(),
+                Bytes::from("2"),
+                WriteOptions {
+                    flags: 0,
+                    expire: 0,
+                },
+            )
+            .await
+            .expect("Can append");
+
+        clock.advance(10);
+        let result = storage.get("key".to_string()).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+    }
+
+    // touch
+    #[tokio::test]
+    async fn test_touch_if_absent() {
+        let storage = HashMapStorage::default();
+
+        let result = storage.touch("key".to_string(), 10).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_touch_extends_expire() {
+        let (storage, clock) = storage_with_clock();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 10,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options)
+            .await
+            .expect("Can set");
+
+        clock.advance(5);
+        let result = storage.touch("key".to_string(), 10).await;
+        assert_eq!(result, Ok(MemcachedResponse::Touched));
 
+        clock.advance(9);
+        assert!(storage.get("key".to_string()).await.is_ok());
+
+        clock.advance(1);
         let result = storage.get("key".to_string()).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_get_and_touch() {
+        let (storage, clock) = storage_with_clock();
+
+        let options = WriteOptions {
+            flags: 3,
+            expire: 10,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options)
+            .await
+            .expect("Can set");
 
+        let result = storage
+            .get_and_touch(vec!["key".to_string(), "absent".to_string()], 0)
+            .await;
         assert_eq!(
             result,
-            Ok(MemcachedResponse::Value {
+            Ok(MemcachedResponse::Values(vec![MemcachedValue {
                 key: "key".to_string(),
-                flags: 0,
-                expire: Duration::from_secs(0),
-                value: "value2".to_string()
-            })
+                flags: 3,
+                value: Bytes::from("value"),
+                cas: Some(1)
+            }]))
+        );
+
+        clock.advance(100);
+        assert!(storage.get("key".to_string()).await.is_ok());
+    }
+
+    // flush
+    #[tokio::test]
+    async fn test_flush_immediately() {
+        let (storage, _) = storage_with_clock();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+
+        let result = storage.flush(0).await;
+        assert_eq!(result, Ok(MemcachedResponse::Ok));
+
+        let result = storage.get("key".to_string()).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+
+        storage
+            .add("key".to_string(), Bytes::from("value2"), options)
+            .await
+            .expect("Can add");
+        assert!(storage.get("key".to_string()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_flush_with_delay() {
+        let (storage, clock) = storage_with_clock();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("old".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+
+        storage.flush(10).await.expect("Can flush");
+        clock.advance(5);
+        storage
+            .set("middle".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+        assert!(storage.get("old".to_string()).await.is_ok());
+
+        clock.advance(5);
+        storage
+            .set("new".to_string(), Bytes::from("value"), options)
+            .await
+            .expect("Can set");
+
+        let result = storage.get("old".to_string()).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+        let result = storage.get("middle".to_string()).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+        assert!(storage.get("new".to_string()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_flush_removes_items_from_statistics() {
+        let (storage, clock) = storage_with_clock();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("v1"), options.clone())
+            .await
+            .expect("Can set");
+        storage.flush(0).await.expect("Can flush");
+        assert_eq!(stat(&storage, "curr_items"), 0);
+        assert_eq!(stat(&storage, "bytes"), 0);
+        // Only the reaper removes the items.
+        assert_eq!(stat(&storage, "reclaimed"), 0);
+        assert_eq!(storage.reap().await, 1);
+
+        storage
+            .set("key".to_string(), Bytes::from("v2"), options)
+            .await
+            .expect("Can set");
+        storage.flush(10).await.expect("Can flush");
+        let live = |stats: Vec<(String, String)>| {
+            stats
+                .into_iter()
+                .filter(|(name, _)| name == "curr_items" || name == "bytes")
+                .map(|(_, value)| value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(live(table(storage.statistics().await)), vec!["5", "1"]);
+
+        clock.advance(10);
+        assert_eq!(live(table(storage.statistics().await)), vec!["0", "0"]);
+        assert!(table(storage.statistics_for("items").await).is_empty());
+
+        // A later delayed flush does not bring back what the last one invalidated.
+        storage.flush(10).await.expect("Can flush");
+        assert_eq!(live(table(storage.statistics().await)), vec!["0", "0"]);
+        assert_eq!(
+            storage.get("key".to_string()).await,
+            Err(MemcachedError::NotFound)
+        );
+        assert_eq!(stat(&storage, "reclaimed"), 1);
+    }
+
+    // add
+    #[tokio::test]
+    async fn test_add_if_absent() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .add("key".to_string(), Bytes::from("value"), options)
+            .await
+            .expect("Can add");
+    }
+
+    #[tokio::test]
+    async fn test_add_if_exists() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+
+        let result = storage
+            .add("key".to_string(), Bytes::from("value2"), options)
+            .await;
+        assert_eq!(result, Err(MemcachedError::NotStored));
+    }
+
+    // replace
+    #[tokio::test]
+    async fn test_replace_if_absent() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+
+        let result = storage
+            .replace("key".to_string(), Bytes::from("value"), options)
+            .await;
+        assert_eq!(result, Err(MemcachedError::NotStored));
+    }
+
+    #[tokio::test]
+    async fn test_replace_if_exists() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+
+        storage
+            .replace("key".to_string(), Bytes::from("value2"), options)
+            .await
+            .expect("Can replace");
+
+        let result = storage.get("key".to_string()).await;
+
+        assert_eq!(
+            result,
+            Ok(MemcachedResponse::Value(MemcachedValue {
+                key: "key".to_string(),
+                flags: 0,
+                value: Bytes::from("value2"),
+                cas: Some(2)
+            }))
+        );
+    }
+
+    // append
+    #[tokio::test]
+    async fn test_append_if_absent() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+
+        let result = storage
+            .append("key".to_string(), Bytes::from("value"), options)
+            .await;
+        assert_eq!(result, Err(MemcachedError::NotStored));
+    }
+
+    #[tokio::test]
+    async fn test_append_if_exists() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+
+        storage
+            .append("key".to_string(), Bytes::from("value2"), options)
+            .await
+            .expect("Can append");
+
+        let result = storage.get("key".to_string()).await;
+
+        assert_eq!(
+            result,
+            Ok(MemcachedResponse::Value(MemcachedValue {
+                key: "key".to_string(),
+                flags: 0,
+                value: Bytes::from("valuevalue2"),
+                cas: Some(2)
+            }))
+        );
+    }
+
+    // prepend
+    #[tokio::test]
+    async fn test_prepend_if_absent() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+
+        let result = storage
+            .prepend("key".to_string(), Bytes::from("value"), options)
+            .await;
+        assert_eq!(result, Err(MemcachedError::NotStored));
+    }
+
+    #[tokio::test]
+    async fn test_prepend_if_exists() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+
+        storage
+            .prepend("key".to_string(), Bytes::from("value2"), options)
+            .await
+            .expect("Can prepend");
+
+        let result = storage.get("key".to_string()).await;
+
+        assert_eq!(
+            result,
+            Ok(MemcachedResponse::Value(MemcachedValue {
+                key: "key".to_string(),
+                flags: 0,
+                value: Bytes::from("value2value"),
+                cas: Some(2)
+            }))
+        );
+    }
+
+    // delete
+    #[tokio::test]
+    async fn test_delete_if_absent() {
+        let storage = HashMapStorage::default();
+
+        let result = storage.delete("key".to_string()).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_delete_if_exists() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options)
+            .await
+            .expect("Can set");
+
+        storage.delete("key".to_string()).await.expect("Can delete");
+
+        let result = storage.get("key".to_string()).await;
+
+        assert_eq!(result, Err(MemcachedError::NotFound));
+    }
+
+    // increment
+    #[tokio::test]
+    async fn test_increment_if_absent() {
+        let storage = HashMapStorage::default();
+
+        let result = storage.increment("key".to_string(), 5).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_increment_if_exists() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("100"), options.clone())
+            .await
+            .expect("Can set");
+
+        let result = storage.increment("key".to_string(), 5).await;
+        assert_eq!(result, Ok(MemcachedResponse::Number(105)));
+
+        let result = storage.get("key".to_string()).await;
+
+        assert_eq!(
+            result,
+            Ok(MemcachedResponse::Value(MemcachedValue {
+                key: "key".to_string(),
+                flags: 0,
+                value: Bytes::from("105"),
+                cas: Some(2)
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_increment_cannot_parse_as_integer() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+
+        let result = storage.increment("key".to_string(), 5).await;
+
+        assert_eq!(result, Err(MemcachedError::FailedToParseInteger));
+
+        let result = storage.get("key".to_string()).await;
+
+        assert_eq!(
+            result,
+            Ok(MemcachedResponse::Value(MemcachedValue {
+                key: "key".to_string(),
+                flags: 0,
+                value: Bytes::from("value"),
+                cas: Some(1)
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_increment_wraps_at_u64_max() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set(
+                "key".to_string(),
+                Bytes::from(u64::MAX.to_string()),
+                options,
+            )
+            .await
+            .expect("Can set");
+
+        let result = storage.increment("key".to_string(), 2).await;
+        assert_eq!(result, Ok(MemcachedResponse::Number(1)));
+    }
+
+    #[tokio::test]
+    async fn test_increment_rejects_negative_value() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("-1"), options)
+            .await
+            .expect("Can set");
+
+        let result = storage.increment("key".to_string(), 1).await;
+        assert_eq!(result, Err(MemcachedError::FailedToParseInteger));
+    }
+
+    // decrement
+    #[tokio::test]
+    async fn test_decrement_if_absent() {
+        let storage = HashMapStorage::default();
+
+        let result = storage.decrement("key".to_string(), 5).await;
+        assert_eq!(result, Err(MemcachedError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_decrement_if_exists() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("100"), options.clone())
+            .await
+            .expect("Can set");
+
+        let result = storage.decrement("key".to_string(), 5).await;
+        assert_eq!(result, Ok(MemcachedResponse::Number(95)));
+
+        let result = storage.get("key".to_string()).await;
+
+        assert_eq!(
+            result,
+            Ok(MemcachedResponse::Value(MemcachedValue {
+                key: "key".to_string(),
+                flags: 0,
+                value: Bytes::from("95"),
+                cas: Some(2)
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decrement_clamps_at_zero() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("3"), options)
+            .await
+            .expect("Can set");
+
+        let result = storage.decrement("key".to_string(), 5).await;
+        assert_eq!(result, Ok(MemcachedResponse::Number(0)));
+    }
+
+    #[tokio::test]
+    async fn test_decrement_cannot_parse_as_integer() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+
+        let result = storage.decrement("key".to_string(), 5).await;
+
+        assert_eq!(result, Err(MemcachedError::FailedToParseInteger));
+
+        let result = storage.get("key".to_string()).await;
+
+        assert_eq!(
+            result,
+            Ok(MemcachedResponse::Value(MemcachedValue {
+                key: "key".to_string(),
+                flags: 0,
+                value: Bytes::from("value"),
+                cas: Some(1)
+            }))
+        );
+    }
+
+    fn stat(storage: &HashMapStorage, name: &str) -> u64 {
+        storage
+            .report()
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .and_then(|(_, value)| value.parse().ok())
+            .expect("Known statistic")
+    }
+
+    #[tokio::test]
+    async fn test_statistics_count_commands() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("1"), options.clone())
+            .await
+            .expect("Can set");
+        storage
+            .add("key".to_string(), Bytes::from("2"), options)
+            .await
+            .expect_err("Cannot add");
+        storage.get("key".to_string()).await.expect("Can get");
+        storage
+            .get_multi(vec!["key".to_string(), "absent".to_string()])
+            .await
+            .expect("Can get multi");
+        storage
+            .increment("key".to_string(), 1)
+            .await
+            .expect("Can increment");
+        storage
+            .decrement("absent".to_string(), 1)
+            .await
+            .expect_err("Cannot decrement");
+        storage.delete("key".to_string()).await.expect("Can delete");
+        storage
+            .delete("key".to_string())
+            .await
+            .expect_err("Cannot delete twice");
+
+        assert_eq!(stat(&storage, "cmd_set"), 2);
+        assert_eq!(stat(&storage, "cmd_get"), 3);
+        assert_eq!(stat(&storage, "get_hits"), 2);
+        assert_eq!(stat(&storage, "get_misses"), 1);
+        assert_eq!(stat(&storage, "incr_hits"), 1);
+        assert_eq!(stat(&storage, "decr_misses"), 1);
+        assert_eq!(stat(&storage, "delete_hits"), 1);
+        assert_eq!(stat(&storage, "delete_misses"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_statistics_track_items_and_bytes() {
+        let storage = HashMapStorage::default();
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key1".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+        storage
+            .set("key2".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+        assert_eq!(stat(&storage, "curr_items"), 2);
+        assert_eq!(stat(&storage, "bytes"), 18);
+
+        storage
+            .set("key1".to_string(), Bytes::from("v"), options.clone())
+            .await
+            .expect("Can set");
+        storage
+            .append("key2".to_string(), Bytes::from("++"), options)
+            .await
+            .expect("Can append");
+        assert_eq!(stat(&storage, "curr_items"), 2);
+        assert_eq!(stat(&storage, "total_items"), 4);
+        assert_eq!(stat(&storage, "bytes"), 16);
+
+        storage
+            .delete("key2".to_string())
+            .await
+            .expect("Can delete");
+        assert_eq!(stat(&storage, "curr_items"), 1);
+        assert_eq!(stat(&storage, "bytes"), 5);
+    }
+
+    fn table(res: MemcachedResult) -> Vec<(String, String)> {
+        match res {
+            Ok(MemcachedResponse::Statistics(stats)) => stats,
+            res => panic!("Not a statistics table: {res:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_statistics_items_and_sizes() {
+        let (storage, clock) = storage_with_clock();
+
+        assert_eq!(table(storage.statistics_for("items").await), vec![]);
+
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        storage
+            .set("key1".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+        clock.advance(10);
+        storage
+            .set("key2".to_string(), Bytes::from(vec![b'x'; 40]), options)
+            .await
+            .expect("Can set");
+
+        let items = table(storage.statistics_for("items").await);
+        for (name, value) in [
+            ("items:1:number", "1"),
+            ("items:1:age", "10"),
+            ("items:2:number", "1"),
+            ("items:2:age", "0"),
+        ] {
+            assert!(items.contains(&(name.to_string(), value.to_string())));
+        }
+
+        assert_eq!(
+            table(storage.statistics_for("sizes").await),
+            vec![
+                ("32".to_string(), "1".to_string()),
+                ("64".to_string(), "1".to_string()),
+            ]
+        );
+        assert_eq!(
+            storage.statistics_for("unknown").await,
+            Err(MemcachedError::NoExistenceCommand)
         );
     }
 
-    // append
     #[tokio::test]
-    async fn test_append_if_absent() {
+    async fn test_reset_statistics_keeps_gauges() {
         let storage = HashMapStorage::default();
 
         let options = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: 0,
         };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options)
+            .await
+            .expect("Can set");
+        storage.get("key".to_string()).await.expect("Can get");
 
-        let result = storage
-            .append("key".to_string(), "value".to_string(), options)
-            .await;
-        assert_eq!(result, Err(MemcachedError::NotFound));
+        assert_eq!(
+            storage.reset_statistics().await,
+            Ok(MemcachedResponse::Reset)
+        );
+        assert_eq!(stat(&storage, "cmd_set"), 0);
+        assert_eq!(stat(&storage, "get_hits"), 0);
+        assert_eq!(stat(&storage, "total_items"), 0);
+        assert_eq!(stat(&storage, "curr_items"), 1);
+        assert_eq!(stat(&storage, "bytes"), 8);
+    }
+
+    fn meta_item(res: MemcachedResult) -> MemcachedItem {
+        match res {
+            Ok(MemcachedResponse::Item(item)) => item,
+            res => panic!("Not an item: {res:?}"),
+        }
     }
 
     #[tokio::test]
-    async fn test_append_if_exists() {
-        let storage = HashMapStorage::default();
+    async fn test_meta_get_reports_ttl_and_last_access() {
+        let (storage, clock) = storage_with_clock();
 
         let options = WriteOptions {
-            flags: 0,
-            expire: Duration::from_secs(0),
+            flags: 3,
+            expire: 100,
         };
         storage
-            .set("key".to_string(), "value".to_string(), options.clone())
+            .set("key".to_string(), Bytes::from("value"), options)
             .await
             .expect("Can set");
+        clock.advance(10);
 
-        storage
-            .append("key".to_string(), "value2".to_string(), options)
-            .await
-            .expect("Can append");
-
-        let result = storage.get("key".to_string()).await;
+        let item = meta_item(storage.meta_get("key".to_string(), None).await);
+        assert_eq!(item.value.flags, 3);
+        assert_eq!(item.ttl, Some(90));
+        assert_eq!(item.last_access, 10);
 
+        clock.advance(5);
+        let item = meta_item(storage.meta_get("key".to_string(), Some(0)).await);
+        assert_eq!(item.ttl, None);
+        assert_eq!(item.last_access, 5);
         assert_eq!(
-            result,
-            Ok(MemcachedResponse::Value {
-                key: "key".to_string(),
-                flags: 0,
-                expire: Duration::from_secs(0),
-                value: "valuevalue2".to_string()
-            })
+            storage.meta_get("absent".to_string(), None).await,
+            Err(MemcachedError::NotFound)
         );
     }
 
-    // prepend
     #[tokio::test]
-    async fn test_prepend_if_absent() {
+    async fn test_meta_delete_invalidates() {
         let storage = HashMapStorage::default();
 
         let options = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: 0,
+        };
+        storage
+            .set("key".to_string(), Bytes::from("value"), options.clone())
+            .await
+            .expect("Can set");
+        let invalidate = MetaDeleteOptions {
+            invalidate: true,
+            ..MetaDeleteOptions::default()
         };
+        assert_eq!(
+            storage.meta_delete("key".to_string(), invalidate).await,
+            Ok(MemcachedResponse::Deleted)
+        );
 
-        let result = storage
-            .prepend("key".to_string(), "value".to_string(), options)
-            .await;
-        assert_eq!(result, Err(MemcachedError::NotFound));
+        let first = meta_item(storage.meta_get("key".to_string(), None).await);
+        assert!(first.stale && first.win && !first.claimed);
+        let second = meta_item(storage.meta_get("key".to_string(), None).await);
+        assert!(second.stale && !second.win && second.claimed);
+
+        let stale_cas = second.value.cas.expect("Has cas") - 1;
+        let recache = MetaSetOptions {
+            compare_cas: Some(stale_cas),
+            invalidate: true,
+            ..MetaSetOptions::default()
+        };
+        let item = meta_item(
+            storage
+                .meta_set(
+                    "key".to_string(),
+                    Bytes::from("old"),
+                    options.clone(),
+                    recache,
+                )
+                .await,
+        );
+        assert!(item.stale);
+
+        let item = meta_item(
+            storage
+                .meta_set(
+                    "key".to_string(),
+                    Bytes::from("new"),
+                    options,
+                    MetaSetOptions::default(),
+                )
+                .await,
+        );
+        assert!(!item.stale);
+        assert_eq!(
+            storage
+                .meta_delete("key".to_string(), MetaDeleteOptions::default())
+                .await,
+            Ok(MemcachedResponse::Deleted)
+        );
+        assert_eq!(
+            storage.get("key".to_string()).await,
+            Err(MemcachedError::NotFound)
+        );
     }
 
     #[tokio::test]
-    async fn test_prepend_if_exists() {
+    async fn test_meta_get_takes_write_lock_only_for_win() {
         let storage = HashMapStorage::default();
-
         let options = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: 0,
         };
         storage
-            .set("key".to_string(), "value".to_string(), options.clone())
+            .set("key".to_string(), Bytes::from("value"), options)
             .await
             .expect("Can set");
-
+        let reader = storage.shard("key").hash_map.read().await;
+
+        let wait = Duration::from_millis(50);
+        let meta_get = storage.meta_get("key".to_string(), None);
+        assert!(tokio::time::timeout(wait, meta_get).await.is_ok());
+        let invalidate = MetaDeleteOptions {
+            invalidate: true,
+            ..MetaDeleteOptions::default()
+        };
+        let meta_delete = storage.meta_delete("key".to_string(), invalidate.clone());
+        assert!(tokio::time::timeout(wait, meta_delete).await.is_err());
+        drop(reader);
         storage
-            .prepend("key".to_string(), "value2".to_string(), options)
+            .meta_delete("key".to_string(), invalidate)
             .await
-            .expect("Can prepend");
+            .expect("Can invalidate");
+
+        let reader = storage.shard("key").hash_map.read().await;
+        let meta_get = storage.meta_get("key".to_string(), None);
+        assert!(tokio::time::timeout(wait, meta_get).await.is_err());
+        drop(reader);
+        let first = meta_item(storage.meta_get("key".to_string(), None).await);
+        assert!(first.win);
+
+        let _reader = storage.shard("key").hash_map.read().await;
+        let meta_get = storage.meta_get("key".to_string(), None);
+        let second = meta_item(
+            tokio::time::timeout(wait, meta_get)
+                .await
+                .expect("Not blocked"),
+        );
+        assert!(second.claimed && !second.win);
+        assert_eq!(stat(&storage, "get_hits"), 3);
+    }
 
-        let result = storage.get("key".to_string()).await;
+    #[tokio::test]
+    async fn test_meta_set_modes_and_cas() {
+        let storage = HashMapStorage::default();
 
+        let options = WriteOptions {
+            flags: 0,
+            expire: 0,
+        };
+        let mode = |mode| MetaSetOptions {
+            mode,
+            ..MetaSetOptions::default()
+        };
         assert_eq!(
-            result,
-            Ok(MemcachedResponse::Value {
-                key: "key".to_string(),
-                flags: 0,
-                expire: Duration::from_secs(0),
-                value: "value2value".to_string()
-            })
+            storage
+                .meta_set(
+                    "key".to_string(),
+                    Bytes::from("x"),
+                    options.clone(),
+                    mode(MetaSetMode::Append),
+                )
+                .await,
+            Err(MemcachedError::NotStored)
+        );
+        let item = meta_item(
+            storage
+                .meta_set(
+                    "key".to_string(),
+                    Bytes::from("value"),
+                    options.clone(),
+                    mode(MetaSetMode::Add),
+                )
+                .await,
+        );
+        let item = meta_item(
+            storage
+                .meta_set(
+                    "key".to_string(),
+                    Bytes::from("!"),
+                    options.clone(),
+                    MetaSetOptions {
+                        mode: MetaSetMode::Append,
+                        compare_cas: item.value.cas,
+                        invalidate: false,
+                    },
+                )
+                .await,
+        );
+        assert_eq!(item.value.value, Bytes::from("value!"));
+        assert_eq!(
+            storage
+                .meta_set(
+                    "key".to_string(),
+                    Bytes::from("x"),
+                    options,
+                    MetaSetOptions {
+                        compare_cas: Some(0),
+                        ..MetaSetOptions::default()
+                    },
+                )
+                .await,
+            Err(MemcachedError::Exists)
         );
+        assert_eq!(stat(&storage, "bytes"), 9);
     }
 
-    // delete
     #[tokio::test]
-    async fn test_delete_if_absent() {
+    async fn test_meta_arithmetic_autovivify() {
         let storage = HashMapStorage::default();
 
-        let result = storage.delete("key".to_string()).await;
-        assert_eq!(result, Err(MemcachedError::NotFound));
+        assert_eq!(
+            storage
+                .meta_arithmetic("counter".to_string(), MetaArithmeticOptions::default())
+                .await,
+            Err(MemcachedError::NotFound)
+        );
+        let vivify = MetaArithmeticOptions {
+            initial: 10,
+            autovivify: Some(0),
+            ..MetaArithmeticOptions::default()
+        };
+        let item = meta_item(
+            storage
+                .meta_arithmetic("counter".to_string(), vivify.clone())
+                .await,
+        );
+        assert_eq!(item.value.value, Bytes::from("10"));
+
+        let item = meta_item(
+            storage
+                .meta_arithmetic(
+                    "counter".to_string(),
+                    MetaArithmeticOptions {
+                        mode: MetaArithmeticMode::Decrement,
+                        delta: 3,
+                        ..vivify
+                    },
+                )
+                .await,
+        );
+        assert_eq!(item.value.value, Bytes::from("7"));
     }
 
     #[tokio::test]
-    async fn test_delete_if_exists() {
-        let storage = HashMapStorage::default();
-
-        let options = WriteOptions {
+    async fn test_reaper_reclaims_expired_items() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let storage = HashMapStorage::new(
+            StorageOptions {
+                reaper_batch_size: 2,
+                ..StorageOptions::default()
+            },
+            clock.clone(),
+        )
+        .unwrap();
+        let expiring = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: 10,
         };
+        for key in ["read", "unread", "touched"] {
+            storage
+                .set(key.to_string(), Bytes::from("value"), expiring.clone())
+                .await
+                .unwrap();
+        }
         storage
-            .set("key".to_string(), "value".to_string(), options)
+            .set(
+                "forever".to_string(),
+                Bytes::from("value"),
+                WriteOptions {
+                    flags: 0,
+                    expire: 0,
+                },
+            )
             .await
-            .expect("Can set");
-
-        storage.delete("key".to_string()).await.expect("Can delete");
-
-        let result = storage.get("key".to_string()).await;
+            .unwrap();
+        storage.get("read".to_string()).await.unwrap();
+        storage.touch("touched".to_string(), 100).await.unwrap();
+
+        assert_eq!(storage.reap().await, 0);
+        clock.advance(10);
+        assert_eq!(storage.reap().await, 2);
+        assert_eq!(storage.reap().await, 0);
+
+        assert_eq!(stat(&storage, "reclaimed"), 2);
+        assert_eq!(stat(&storage, "expired_unfetched"), 1);
+        assert_eq!(stat(&storage, "curr_items"), 2);
+        let mut remaining = 0;
+        for shard in &storage.shards {
+            remaining += shard.hash_map.read().await.len();
+        }
+        assert_eq!(remaining, 2);
 
-        assert_eq!(result, Err(MemcachedError::NotFound));
+        clock.advance(100);
+        assert_eq!(storage.reap().await, 1);
+        assert_eq!(stat(&storage, "reclaimed"), 3);
+        assert_eq!(stat(&storage, "expired_unfetched"), 1);
+        assert!(storage.get("forever".to_string()).await.is_ok());
     }
 
-    // increment
     #[tokio::test]
-    async fn test_increment_if_absent() {
-        let storage = HashMapStorage::default();
+    async fn test_reaper_skips_replaced_and_deleted_items() {
+        let (storage, clock) = storage_with_clock();
+        let expiring = WriteOptions {
+            flags: 0,
+            expire: 10,
+        };
+        for key in ["replaced", "deleted"] {
+            storage
+                .set(key.to_string(), Bytes::from("value"), expiring.clone())
+                .await
+                .unwrap();
+        }
+        storage
+            .set(
+                "replaced".to_string(),
+                Bytes::from("value"),
+                WriteOptions {
+                    flags: 0,
+                    expire: 0,
+                },
+            )
+            .await
+            .unwrap();
+        storage.delete("deleted".to_string()).await.unwrap();
 
-        let result = storage.increment("key".to_string(), 5).await;
-        assert_eq!(result, Err(MemcachedError::NotFound));
+        clock.advance(10);
+        assert_eq!(storage.reap().await, 0);
+        assert_eq!(stat(&storage, "reclaimed"), 0);
+        assert!(storage.get("replaced".to_string()).await.is_ok());
     }
 
     #[tokio::test]
-    async fn test_increment_if_exists() {
-        let storage = HashMapStorage::default();
-
+    async fn test_reaper_reclaims_flushed_items() {
+        let (storage, clock) = storage_with_clock();
         let options = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: 0,
         };
+        for key in ["a", "b"] {
+            storage
+                .set(key.to_string(), Bytes::from("value"), options.clone())
+                .await
+                .unwrap();
+        }
+
+        storage.flush(10).await.unwrap();
+        assert_eq!(storage.reap().await, 0);
+        clock.advance(10);
+        assert_eq!(storage.reap().await, 2);
+        assert_eq!(storage.reap().await, 0);
+        assert_eq!(stat(&storage, "curr_items"), 0);
+        assert_eq!(stat(&storage, "reclaimed"), 2);
+    }
+
+    #[test]
+    fn test_reaper_options_are_validated() {
+        for options in [
+            StorageOptions {
+                reaper_interval: Duration::ZERO,
+                ..StorageOptions::default()
+            },
+            StorageOptions {
+                reaper_batch_size: 0,
+                ..StorageOptions::default()
+            },
+        ] {
+            assert!(HashMapStorage::new(options, Arc::new(ManualClock::new(NOW))).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reaper_task_stops_with_storage() {
+        let storage = Arc::new(
+            HashMapStorage::new(
+                StorageOptions {
+                    reaper_interval: Duration::from_millis(1),
+                    ..StorageOptions::default()
+                },
+                Arc::new(ManualClock::new(NOW)),
+            )
+            .unwrap(),
+        );
+        let reaper = storage.start_reaper();
         storage
-            .set("key".to_string(), "100".to_string(), options.clone())
+            .set(
+                "key".to_string(),
+                Bytes::from("value"),
+                WriteOptions {
+                    flags: 0,
+                    expire: -1,
+                },
+            )
             .await
-            .expect("Can set");
+            .unwrap();
+        while stat(&storage, "reclaimed") == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        drop(storage);
+        reaper.await.expect("Reaper stops cleanly");
+    }
+
+    /// Charge of an item with a four byte key, a four byte value and a deadline; the smallest
+    /// chunk of the storages below.
+    const SMALL_CHARGE: u64 = charge_for(4, 4, 4);
+
+    fn limited_storage(items: u64, evictions: bool) -> HashMapStorage {
+        limited_storage_with_clock(items, evictions, Arc::new(SystemClock))
+    }
+
+    fn limited_storage_with_clock(
+        items: u64,
+        evictions: bool,
+        clock: Arc<dyn Clock>,
+    ) -> HashMapStorage {
+        HashMapStorage::new(
+            StorageOptions {
+                // One page per entry with a four byte key and a four byte value.
+                max_bytes: items * SMALL_CHARGE,
+                slab_chunk_size: SMALL_CHARGE - charge_for(3, 0, 0),
+                slab_page_size: SMALL_CHARGE,
+                evictions,
+                shards: 1,
+                ..StorageOptions::default()
+            },
+            clock,
+        )
+        .unwrap()
+    }
+
+    /// Two slab classes, the first with two chunks per page for a four byte key and value, the
+    /// second with one.
+    fn two_class_storage(pages: u64) -> HashMapStorage {
+        let page_size = 2 * SMALL_CHARGE.next_multiple_of(8);
+        HashMapStorage::new(
+            StorageOptions {
+                max_bytes: pages * page_size,
+                slab_chunk_size: SMALL_CHARGE - charge_for(3, 0, 0),
+                slab_growth_factor: 2.0,
+                slab_page_size: page_size,
+                shards: 1,
+                ..StorageOptions::default()
+            },
+            Arc::new(SystemClock),
+        )
+        .unwrap()
+    }
+
+    async fn slab_stat(storage: &HashMapStorage, name: &str) -> u64 {
+        table(storage.statistics_for("slabs").await)
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map_or(0, |(_, value)| {
+                value.parse().expect("Statistic is a number")
+            })
+    }
 
+    /// Needs a chunk of the second class of `two_class_storage`.
+    const LARGE: &str = "01234567890123456789012345678901234567890123456789";
+
+    async fn store(storage: &HashMapStorage, key: &str, value: &'static str) -> MemcachedResult {
         storage
-            .increment("key".to_string(), 5)
+            .set(
+                key.to_string(),
+                Bytes::from(value),
+                WriteOptions {
+                    flags: 0,
+                    expire: 0,
+                },
+            )
             .await
-            .expect("Can increment");
+    }
 
-        let result = storage.get("key".to_string()).await;
+    #[tokio::test]
+    async fn test_memory_limit_evicts_least_recently_used() {
+        let storage = limited_storage(3, true);
+
+        for key in ["key1", "key2", "key3"] {
+            store(&storage, key, "valu").await.unwrap();
+        }
+        storage.get("key1".to_string()).await.unwrap();
+        store(&storage, "key4", "valu").await.unwrap();
 
         assert_eq!(
-            result,
-            Ok(MemcachedResponse::Value {
-                key: "key".to_string(),
-                flags: 0,
-                expire: Duration::from_secs(0),
-                value: "105".to_string()
-            })
+            storage.get("key2".to_string()).await,
+            Err(MemcachedError::NotFound)
         );
+        for key in ["key1", "key3", "key4"] {
+            assert!(storage.get(key.to_string()).await.is_ok());
+        }
+        assert_eq!(stat(&storage, "evictions"), 1);
+        assert_eq!(stat(&storage, "curr_items"), 3);
+        assert_eq!(stat(&storage, "limit_maxbytes"), 3 * SMALL_CHARGE);
     }
 
     #[tokio::test]
-    async fn test_increment_cannot_parse_as_integer() {
-        let storage = HashMapStorage::default();
+    async fn test_growing_item_moves_to_larger_class() {
+        let storage = two_class_storage(2);
 
-        let options = WriteOptions {
-            flags: 0,
-            expire: Duration::from_secs(0),
-        };
+        store(&storage, "key1", "valu").await.unwrap();
+        store(&storage, "key2", "valu").await.unwrap();
+        store(&storage, "key3", LARGE).await.unwrap();
         storage
-            .set("key".to_string(), "value".to_string(), options.clone())
+            .append(
+                "key1".to_string(),
+                Bytes::from(LARGE),
+                WriteOptions {
+                    flags: 0,
+                    expire: 0,
+                },
+            )
             .await
-            .expect("Can set");
-
-        let result = storage.increment("key".to_string(), 5).await;
+            .unwrap();
 
-        assert_eq!(result, Err(MemcachedError::FailedToParseInteger));
+        // Only the larger class was full; the item's old chunk is free again.
+        assert_eq!(
+            storage.get("key3".to_string()).await,
+            Err(MemcachedError::NotFound)
+        );
+        assert!(storage.get("key1".to_string()).await.is_ok());
+        assert!(storage.get("key2".to_string()).await.is_ok());
+        assert_eq!(stat(&storage, "evictions"), 1);
+        assert_eq!(slab_stat(&storage, "1:used_chunks").await, 1);
+        assert_eq!(slab_stat(&storage, "1:free_chunks").await, 1);
+        assert_eq!(slab_stat(&storage, "2:used_chunks").await, 1);
+        let items = table(storage.statistics_for("items").await);
+        for (name, value) in [
+            ("items:1:number", "1"),
+            ("items:1:evicted", "0"),
+            ("items:2:number", "1"),
+            ("items:2:evicted", "1"),
+        ] {
+            assert!(items.contains(&(name.to_string(), value.to_string())));
+        }
+    }
 
-        let result = storage.get("key".to_string()).await;
+    #[tokio::test]
+    async fn test_memory_limit_without_evictions() {
+        let storage = limited_storage(2, false);
 
+        store(&storage, "key1", "valu").await.unwrap();
+        store(&storage, "key2", "valu").await.unwrap();
         assert_eq!(
-            result,
-            Ok(MemcachedResponse::Value {
-                key: "key".to_string(),
-                flags: 0,
-                expire: Duration::from_secs(0),
-                value: "value".to_string()
-            })
+            store(&storage, "key3", "valu").await,
+            Err(MemcachedError::Server(
+                "out of memory storing object".to_string()
+            ))
+        );
+        assert_eq!(
+            store(&storage, "key1", "vale").await,
+            Ok(MemcachedResponse::Stored)
         );
+
+        assert_eq!(stat(&storage, "evictions"), 0);
+        assert_eq!(stat(&storage, "curr_items"), 2);
+        assert!(table(storage.statistics_for("settings").await)
+            .contains(&("evictions".to_string(), "off".to_string())));
     }
 
-    // decrement
     #[tokio::test]
-    async fn test_decrement_if_absent() {
-        let storage = HashMapStorage::default();
+    async fn test_memory_limit_without_evictions_reclaims_dead_items() {
+        let clock = Arc::new(ManualClock::new(NOW));
+        let storage = limited_storage_with_clock(2, false, clock.clone());
+        let fill = || async {
+            for key in ["key1", "key2"] {
+                storage
+                    .set(
+                        key.to_string(),
+                        Bytes::from("valu"),
+                        WriteOptions {
+                            flags: 0,
+                            expire: 10,
+                        },
+                    )
+                    .await
+                    .unwrap();
+            }
+        };
 
-        let result = storage.decrement("key".to_string(), 5).await;
-        assert_eq!(result, Err(MemcachedError::NotFound));
+        fill().await;
+        storage.flush(0).await.unwrap();
+        assert_eq!(
+            store(&storage, "key3", "valu").await,
+            Ok(MemcachedResponse::Stored)
+        );
+
+        storage.flush(0).await.unwrap();
+        fill().await;
+        storage.flush(5).await.unwrap();
+        clock.advance(5);
+        assert_eq!(
+            store(&storage, "key3", "valu").await,
+            Ok(MemcachedResponse::Stored)
+        );
+
+        storage.flush(0).await.unwrap();
+        fill().await;
+        clock.advance(10);
+        assert_eq!(
+            store(&storage, "key3", "valu").await,
+            Ok(MemcachedResponse::Stored)
+        );
+        assert_eq!(stat(&storage, "evictions"), 0);
+        assert_eq!(stat(&storage, "curr_items"), 1);
     }
 
     #[tokio::test]
-    async fn test_decrement_if_exists() {
-        let storage = HashMapStorage::default();
+    async fn test_default_page_fits_largest_accepted_item() {
+        // SLRU items with a deadline hold the most copies of their key.
+        let storage = HashMapStorage::new(
+            StorageOptions {
+                eviction_policy: || Box::new(Slru::default()),
+                ..StorageOptions::default()
+            },
+            Arc::new(SystemClock),
+        )
+        .unwrap();
+        let server = ServerOptions::default();
+        let key = "k".repeat(server.max_key_length);
+
+        for (length, stored) in [
+            (server.max_item_size, true),
+            (server.max_item_size + 1, false),
+        ] {
+            let res = storage
+                .set(
+                    key.clone(),
+                    Bytes::from(vec![b'x'; length]),
+                    WriteOptions {
+                        flags: 0,
+                        expire: 60,
+                    },
+                )
+                .await;
+            assert_eq!(res.is_ok(), stored, "{length} bytes");
+        }
+    }
 
-        let options = WriteOptions {
+    #[test]
+    fn test_slab_options_are_validated() {
+        for options in [
+            StorageOptions {
+                slab_growth_factor: 0.0,
+                ..StorageOptions::default()
+            },
+            StorageOptions {
+                slab_growth_factor: 1.0,
+                ..StorageOptions::default()
+            },
+            StorageOptions {
+                slab_growth_factor: f64::NAN,
+                ..StorageOptions::default()
+            },
+            StorageOptions {
+                slab_chunk_size: 0,
+                ..StorageOptions::default()
+            },
+            StorageOptions {
+                slab_page_size: 0,
+                ..StorageOptions::default()
+            },
+        ] {
+            assert!(HashMapStorage::new(options, Arc::new(SystemClock)).is_err());
+        }
+    }
+
+    /// Moves forward by a second right after the next read, as if it ticked during a call.
+    #[derive(Default)]
+    struct TickingClock {
+        now: AtomicU64,
+        tick: AtomicBool,
+    }
+
+    impl Clock for TickingClock {
+        fn now(&self) -> u64 {
+            let now = self.now.load(Ordering::Relaxed);
+            if self.tick.swap(false, Ordering::Relaxed) {
+                self.now.fetch_add(1, Ordering::Relaxed);
+            }
+            now
+        }
+    }
+
+    #[tokio::test]
+    async fn test_modify_without_evictions_keeps_item_when_clock_ticks() {
+        let clock = Arc::new(TickingClock::default());
+        clock.now.store(NOW, Ordering::Relaxed);
+        let storage = HashMapStorage::new(
+            StorageOptions {
+                max_bytes: 4096,
+                slab_page_size: 2048,
+                evictions: false,
+                shards: 1,
+                ..StorageOptions::default()
+            },
+            clock.clone(),
+        )
+        .unwrap();
+        let expiring = WriteOptions {
             flags: 0,
-            expire: Duration::from_secs(0),
+            expire: 1,
         };
+        let large = Bytes::from(vec![b'x'; 1500]);
         storage
-            .set("key".to_string(), "100".to_string(), options.clone())
+            .set("key".to_string(), Bytes::from("value"), expiring.clone())
             .await
-            .expect("Can set");
-
+            .unwrap();
         storage
-            .decrement("key".to_string(), 5)
+            .set("other".to_string(), large.clone(), expiring.clone())
             .await
-            .expect("Can decrement");
+            .unwrap();
 
-        let result = storage.get("key".to_string()).await;
+        // Both items are live when the append starts and expire while it waits for memory.
+        clock.tick.store(true, Ordering::Relaxed);
+        assert_eq!(
+            storage.append("key".to_string(), large, expiring).await,
+            Err(out_of_memory())
+        );
+        assert_eq!(stat(&storage, "curr_items"), 2);
+    }
 
+    #[tokio::test]
+    async fn test_item_larger_than_page_is_refused() {
+        let storage = limited_storage(1, true);
+
+        store(&storage, "key1", "valu").await.unwrap();
         assert_eq!(
-            result,
-            Ok(MemcachedResponse::Value {
-                key: "key".to_string(),
-                flags: 0,
-                expire: Duration::from_secs(0),
-                value: "95".to_string()
-            })
+            store(&storage, "key2", LARGE).await,
+            Err(MemcachedError::Server(
+                "object too large for cache".to_string()
+            ))
         );
+        assert!(storage.get("key1".to_string()).await.is_ok());
+        assert_eq!(stat(&storage, "evictions"), 0);
     }
 
     #[tokio::test]
-    async fn test_decrement_cannot_parse_as_integer() {
-        let storage = HashMapStorage::default();
+    async fn test_multi_key_operations_across_shards() {
+        let storage = HashMapStorage::new(
+            StorageOptions {
+                shards: 4,
+                ..StorageOptions::default()
+            },
+            Arc::new(SystemClock),
+        )
+        .unwrap();
+        let keys: Vec<String> = (0..32).map(|i| format!("key{i}")).collect();
+        for key in keys.iter().step_by(2) {
+            store(&storage, key, "valu").await.unwrap();
+        }
 
-        let options = WriteOptions {
-            flags: 0,
-            expire: Duration::from_secs(0),
+        assert!(storage.group_by_shard(&keys).len() > 1);
+
+        let expected: Vec<String> = keys.iter().step_by(2).cloned().collect();
+        let found = |res: MemcachedResult| match res {
+            Ok(MemcachedResponse::Values(values)) => values
+                .into_iter()
+                .map(|value| value.key)
+                .collect::<Vec<_>>(),
+            res => panic!("Not values: {res:?}"),
         };
-        storage
-            .set("key".to_string(), "value".to_string(), options.clone())
-            .await
-            .expect("Can set");
+        assert_eq!(found(storage.get_multi(keys.clone()).await), expected);
+        assert_eq!(found(storage.get_and_touch(keys, 100).await), expected);
+        assert_eq!(stat(&storage, "get_hits"), 32);
+        assert_eq!(stat(&storage, "get_misses"), 32);
+        assert_eq!(
+            table(storage.statistics_for("items").await)[0],
+            ("items:1:number".to_string(), "16".to_string())
+        );
+    }
 
-        let result = storage.decrement("key".to_string(), 5).await;
+    #[tokio::test]
+    async fn test_memory_limit_is_shared_by_shards() {
+        let storage = HashMapStorage::new(
+            StorageOptions {
+                max_bytes: 4 * SMALL_CHARGE,
+                slab_chunk_size: SMALL_CHARGE - charge_for(3, 0, 0),
+                slab_page_size: SMALL_CHARGE,
+                shards: 4,
+                ..StorageOptions::default()
+            },
+            Arc::new(SystemClock),
+        )
+        .unwrap();
+
+        // Shards without an item to give up evict from the others.
+        for i in 0..100 {
+            store(&storage, &format!("k{i:03}"), "valu").await.unwrap();
+        }
 
-        assert_eq!(result, Err(MemcachedError::FailedToParseInteger));
+        assert_eq!(stat(&storage, "curr_items"), 4);
+        assert_eq!(stat(&storage, "evictions"), 96);
+        assert_eq!(slab_stat(&storage, "1:total_pages").await, 4);
+        assert_eq!(
+            slab_stat(&storage, "total_malloced").await,
+            4 * SMALL_CHARGE
+        );
+    }
 
-        let result = storage.get("key".to_string()).await;
+    #[tokio::test]
+    async fn test_automove_takes_free_page() {
+        let storage = two_class_storage(2);
+
+        store(&storage, "key1", "valu").await.unwrap();
+        storage.delete("key1".to_string()).await.unwrap();
+        store(&storage, "key2", LARGE).await.unwrap();
+        store(&storage, "key3", LARGE).await.unwrap();
+        assert_eq!(stat(&storage, "evictions"), 1);
+
+        assert!(storage.automove().await);
+        assert_eq!(slab_stat(&storage, "2:total_pages").await, 2);
+        store(&storage, "key4", LARGE).await.unwrap();
+        assert_eq!(stat(&storage, "evictions"), 1);
+        assert_eq!(stat(&storage, "slabs_moved"), 1);
+
+        // Nothing was evicted since.
+        assert!(!storage.automove().await);
+    }
+
+    #[tokio::test]
+    async fn test_automove_evicts_from_idle_class() {
+        let storage = two_class_storage(3);
+
+        for key in ["key1", "key2", "key3", "key4"] {
+            store(&storage, key, "valu").await.unwrap();
+        }
+        store(&storage, "big1", LARGE).await.unwrap();
+        store(&storage, "big2", LARGE).await.unwrap();
+
+        assert!(storage.automove().await);
+        assert_eq!(stat(&storage, "slab_reassign_evictions_nomem"), 2);
+        assert_eq!(stat(&storage, "curr_items"), 3);
+        assert_eq!(slab_stat(&storage, "1:total_pages").await, 1);
+        assert_eq!(slab_stat(&storage, "2:total_pages").await, 2);
+
+        store(&storage, "big3", LARGE).await.unwrap();
+        assert_eq!(stat(&storage, "evictions"), 1);
+        assert!(storage.get("big2".to_string()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_automove_task_stops_with_storage() {
+        let storage = Arc::new(
+            HashMapStorage::new(
+                StorageOptions {
+                    slab_automove_interval: Duration::from_millis(1),
+                    ..StorageOptions::default()
+                },
+                Arc::new(SystemClock),
+            )
+            .unwrap(),
+        );
+        let task = storage.start_slab_automove();
+
+        drop(storage);
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .expect("Task stops")
+            .expect("Task does not panic");
+    }
+
+    #[tokio::test]
+    async fn test_eviction_policy_is_selectable() {
+        let storage = HashMapStorage::new(
+            StorageOptions {
+                max_bytes: 3 * SMALL_CHARGE,
+                // SLRU holds one more copy of each key.
+                slab_chunk_size: SMALL_CHARGE - charge_for(4, 0, 0),
+                slab_page_size: SMALL_CHARGE,
+                shards: 1,
+                eviction_policy: || Box::new(Slru::default()),
+                ..StorageOptions::default()
+            },
+            Arc::new(SystemClock),
+        )
+        .unwrap();
+
+        store(&storage, "key1", "valu").await.unwrap();
+        storage.get("key1".to_string()).await.unwrap();
+        for key in ["key2", "key3", "key4"] {
+            store(&storage, key, "valu").await.unwrap();
+        }
 
+        // Plain LRU would give up key1; SLRU keeps the item that was read.
+        assert!(storage.get("key1".to_string()).await.is_ok());
         assert_eq!(
-            result,
-            Ok(MemcachedResponse::Value {
-                key: "key".to_string(),
-                flags: 0,
-                expire: Duration::from_secs(0),
-                value: "value".to_string()
-            })
+            storage.get("key2".to_string()).await,
+            Err(MemcachedError::NotFound)
         );
+        assert!(table(storage.statistics_for("settings").await)
+            .contains(&("eviction_policy".to_string(), "slru".to_string())));
+        assert!(table(storage.statistics().await)
+            .contains(&("hit_ratio".to_string(), "0.6667".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_charge_counts_the_key_copies_held() {
+        let cases: [(EvictionPolicyFactory, i64, u64); 4] = [
+            (|| Box::new(Lru::default()), 0, 3),
+            (|| Box::new(Lru::default()), 60, 4),
+            (|| Box::new(Slru::default()), 0, 4),
+            (|| Box::new(WTinyLfu::default()), 60, 4),
+        ];
+        for (eviction_policy, expire, key_copies) in cases {
+            let storage = HashMapStorage::new(
+                StorageOptions {
+                    eviction_policy,
+                    ..StorageOptions::default()
+                },
+                Arc::new(SystemClock),
+            )
+            .unwrap();
+            storage
+                .set(
+                    "key".to_string(),
+                    Bytes::from("value"),
+                    WriteOptions { flags: 0, expire },
+                )
+                .await
+                .unwrap();
+
+            let requested: u64 = table(storage.statistics_for("slabs").await)
+                .into_iter()
+                .filter(|(key, _)| key.ends_with(":mem_requested"))
+                .map(|(_, value)| value.parse::<u64>().expect("Statistic is a number"))
+                .sum();
+            assert_eq!(requested, charge_for(key_copies, 3, 5));
+            assert!(key_copies <= MAX_KEY_COPIES);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_policy_state_is_charged_against_memory_limit() {
+        let page_size = 64 * 1024;
+        let storage = |eviction_policy: EvictionPolicyFactory| {
+            HashMapStorage::new(
+                StorageOptions {
+                    max_bytes: 2 * page_size,
+                    slab_page_size: page_size,
+                    evictions: false,
+                    shards: 1,
+                    eviction_policy,
+                    ..StorageOptions::default()
+                },
+                Arc::new(SystemClock),
+            )
+            .unwrap()
+        };
+        let large = "x".repeat(1024);
+
+        let cases: [(EvictionPolicyFactory, bool); 2] = [
+            (|| Box::new(Lru::default()), true),
+            (|| Box::new(WTinyLfu::default()), false),
+        ];
+        for (eviction_policy, fits) in cases {
+            let storage = storage(eviction_policy);
+            store(&storage, "key1", "valu").await.unwrap();
+            let res = storage
+                .set(
+                    "key2".to_string(),
+                    Bytes::from(large.clone()),
+                    WriteOptions {
+                        flags: 0,
+                        expire: 0,
+                    },
+                )
+                .await;
+            assert_eq!(res.is_ok(), fits);
+        }
     }
 }
@@ -1,3 +1,8 @@
+mod expiry;
 mod hash_map_storage;
+mod policy;
+mod slab;
+mod statistics;
 
 pub use hash_map_storage::*;
+pub use policy::*;
@@ -0,0 +1,97 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Keys ordered from least to most recently pushed; the building block of every policy.
+#[derive(Default)]
+pub(crate) struct LruList {
+    ticks: HashMap<String, u64>,
+    order: BTreeMap<u64, String>,
+    next_tick: u64,
+}
+
+impl LruList {
+    /// Copies of each listed key: the tick lookup and the order.
+    pub(crate) const KEY_COPIES: u64 = 2;
+
+    pub(crate) fn len(&self) -> usize {
+        self.ticks.len()
+    }
+
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.ticks.contains_key(key)
+    }
+
+    /// Adds `key` as the most recently used entry, or moves it there.
+    pub(crate) fn push(&mut self, key: &str) {
+        self.next_tick += 1;
+        let tick = self.next_tick;
+        match self.ticks.get_mut(key) {
+            Some(old) => {
+                let key = self.order.remove(old).expect("Listed key has a tick");
+                *old = tick;
+                self.order.insert(tick, key);
+            }
+            None => {
+                self.ticks.insert(key.to_string(), tick);
+                self.order.insert(tick, key.to_string());
+            }
+        }
+    }
+
+    /// Returns whether the key was listed.
+    pub(crate) fn remove(&mut self, key: &str) -> bool {
+        let Some(tick) = self.ticks.remove(key) else {
+            return false;
+        };
+        self.order.remove(&tick);
+        true
+    }
+
+    pub(crate) fn pop_oldest(&mut self) -> Option<String> {
+        let (_, key) = self.order.pop_first()?;
+        self.ticks.remove(&key);
+        Some(key)
+    }
+
+    /// The least recently used key other than `except`.
+    pub(crate) fn oldest_except(&self, except: &str) -> Option<&str> {
+        self.order
+            .values()
+            .map(String::as_str)
+            .find(|key| *key != except)
+    }
+
+    /// The most recently used key other than `except`.
+    pub(crate) fn newest_except(&self, except: &str) -> Option<&str> {
+        self.order
+            .values()
+            .rev()
+            .map(String::as_str)
+            .find(|key| *key != except)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_follows_push_order() {
+        let mut list = LruList::default();
+        for key in ["a", "b", "c"] {
+            list.push(key);
+        }
+
+        assert_eq!(list.oldest_except(""), Some("a"));
+        list.push("a");
+        assert_eq!(list.oldest_except(""), Some("b"));
+        assert_eq!(list.oldest_except("b"), Some("c"));
+        assert_eq!(list.newest_except(""), Some("a"));
+        assert_eq!(list.newest_except("a"), Some("c"));
+        assert!(list.remove("b"));
+        assert!(!list.remove("b"));
+        assert_eq!(list.pop_oldest().as_deref(), Some("c"));
+        assert_eq!(list.pop_oldest().as_deref(), Some("a"));
+        assert_eq!(list.pop_oldest(), None);
+        assert_eq!(list.len(), 0);
+    }
+}
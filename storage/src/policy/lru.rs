@@ -0,0 +1,66 @@
+use super::list::LruList;
+use super::EvictionPolicy;
+
+/// Evicts the least recently used item.
+#[derive(Default)]
+pub struct Lru {
+    list: LruList,
+}
+
+impl EvictionPolicy for Lru {
+    fn name(&self) -> &'static str {
+        "lru"
+    }
+
+    fn insert(&mut self, key: &str) {
+        self.list.push(key);
+    }
+
+    fn access(&mut self, key: &str) {
+        if self.list.contains(key) {
+            self.list.push(key);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.list.remove(key);
+    }
+
+    fn victim(&mut self, except: &str) -> Option<String> {
+        self.list.oldest_except(except).map(str::to_string)
+    }
+
+    fn key_copies(&self) -> u64 {
+        LruList::KEY_COPIES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_victim_is_least_recently_used() {
+        let mut lru = Lru::default();
+        for key in ["a", "b", "c"] {
+            lru.insert(key);
+        }
+
+        assert_eq!(lru.victim("").as_deref(), Some("a"));
+        lru.access("a");
+        assert_eq!(lru.victim("").as_deref(), Some("b"));
+        assert_eq!(lru.victim("b").as_deref(), Some("c"));
+        lru.remove("b");
+        assert_eq!(lru.victim("").as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn test_access_of_removed_key_is_ignored() {
+        let mut lru = Lru::default();
+        lru.insert("a");
+        lru.remove("a");
+        lru.access("a");
+
+        assert_eq!(lru.victim(""), None);
+    }
+}
@@ -0,0 +1,89 @@
+mod list;
+mod lru;
+mod slru;
+mod tiny_lfu;
+
+pub use lru::Lru;
+pub use slru::Slru;
+pub use tiny_lfu::WTinyLfu;
+
+/// Decides which item of a slab class is given up when the class cannot get another chunk.
+///
+/// Every shard has one policy per slab class. The store reports every key of the class it stores,
+/// reads and removes, and asks for a victim when the class is full and no page is left. Calls are
+/// made with the shard locked, so implementations should be cheap.
+pub trait EvictionPolicy: Send {
+    /// Reported in `stats settings`.
+    fn name(&self) -> &'static str;
+
+    /// A key that was not tracked yet has been stored.
+    fn insert(&mut self, key: &str);
+
+    /// A tracked key has been read or overwritten.
+    fn access(&mut self, key: &str);
+
+    /// A key is gone, whether evicted, deleted or expired.
+    fn remove(&mut self, key: &str);
+
+    /// The key to evict next, never `except`. It stays tracked until it is removed.
+    fn victim(&mut self, except: &str) -> Option<String>;
+
+    /// Most copies of a tracked key the policy holds, charged against the memory limit with every
+    /// item.
+    fn key_copies(&self) -> u64;
+
+    /// Bytes the policy uses however many keys it tracks, charged against the memory limit.
+    fn footprint(&self) -> u64 {
+        0
+    }
+}
+
+/// Builds the policy of each slab class of each shard.
+pub type EvictionPolicyFactory = fn() -> Box<dyn EvictionPolicy>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Replays `requests` against a cache of `capacity` items and returns the number of hits.
+    fn replay(policy: &mut dyn EvictionPolicy, capacity: usize, requests: &[String]) -> usize {
+        let mut cached = HashSet::new();
+        let mut hits = 0;
+        for key in requests {
+            if cached.contains(key) {
+                hits += 1;
+                policy.access(key);
+                continue;
+            }
+            if cached.len() == capacity {
+                let victim = policy.victim(key).expect("Full cache has a victim");
+                policy.remove(&victim);
+                cached.remove(&victim);
+            }
+            policy.insert(key);
+            cached.insert(key.clone());
+        }
+        hits
+    }
+
+    #[test]
+    fn test_scans_do_not_flush_frequent_keys() {
+        let mut requests = vec![];
+        for round in 0..50 {
+            for _ in 0..2 {
+                requests.extend((0..5).map(|i| format!("hot{i}")));
+            }
+            requests.extend((0..30).map(|i| format!("scan{round}-{i}")));
+        }
+
+        let lru = replay(&mut Lru::default(), 20, &requests);
+        let slru = replay(&mut Slru::default(), 20, &requests);
+        let tiny_lfu = replay(&mut WTinyLfu::default(), 20, &requests);
+
+        // Plain LRU only hits on the second read of each round.
+        assert_eq!(lru, 50 * 5);
+        assert!(slru > 90 * 5, "slru hits {slru}");
+        assert!(tiny_lfu > 90 * 5, "w-tinylfu hits {tiny_lfu}");
+    }
+}
@@ -0,0 +1,139 @@
+use super::list::LruList;
+use super::EvictionPolicy;
+use std::collections::HashSet;
+
+/// Largest share of items, in percent, kept in the hot segment.
+const HOT_PERCENT: usize = 20;
+/// Largest share of items, in percent, kept in the warm segment.
+const WARM_PERCENT: usize = 40;
+
+/// Segmented LRU in the style of memcached's hot/warm/cold LRU.
+///
+/// New items start hot. Hot items that overflow move to warm if they were read in the meantime
+/// and to cold otherwise, so a scan of one-off reads only ever churns the cold segment. Reading
+/// a cold item promotes it to warm, and evictions come from cold first.
+#[derive(Default)]
+pub struct Slru {
+    hot: LruList,
+    warm: LruList,
+    cold: LruList,
+    /// Hot items read since they were stored.
+    active: HashSet<String>,
+}
+
+impl Slru {
+    fn len(&self) -> usize {
+        self.hot.len() + self.warm.len() + self.cold.len()
+    }
+
+    fn balance(&mut self) {
+        let len = self.len();
+        while self.hot.len() > (len * HOT_PERCENT / 100).max(1) {
+            let Some(key) = self.hot.pop_oldest() else {
+                break;
+            };
+            if self.active.remove(&key) {
+                self.warm.push(&key);
+            } else {
+                self.cold.push(&key);
+            }
+        }
+        while self.warm.len() > (len * WARM_PERCENT / 100).max(1) {
+            let Some(key) = self.warm.pop_oldest() else {
+                break;
+            };
+            self.cold.push(&key);
+        }
+    }
+}
+
+impl EvictionPolicy for Slru {
+    fn name(&self) -> &'static str {
+        "slru"
+    }
+
+    fn insert(&mut self, key: &str) {
+        self.hot.push(key);
+        self.balance();
+    }
+
+    fn access(&mut self, key: &str) {
+        if self.hot.contains(key) {
+            if !self.active.contains(key) {
+                self.active.insert(key.to_string());
+            }
+        } else if self.warm.contains(key) {
+            self.warm.push(key);
+        } else if self.cold.remove(key) {
+            self.warm.push(key);
+            self.balance();
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        let _ = self.hot.remove(key) || self.warm.remove(key) || self.cold.remove(key);
+        self.active.remove(key);
+    }
+
+    fn victim(&mut self, except: &str) -> Option<String> {
+        self.cold
+            .oldest_except(except)
+            .or_else(|| self.warm.oldest_except(except))
+            .or_else(|| self.hot.oldest_except(except))
+            .map(str::to_string)
+    }
+
+    fn key_copies(&self) -> u64 {
+        // A key is in one of the lists and, once read while hot, in the set of read hot items.
+        LruList::KEY_COPIES + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unread_items_are_evicted_first() {
+        let mut slru = Slru::default();
+        for key in ["read", "a", "b", "c", "d"] {
+            slru.insert(key);
+            if key == "read" {
+                slru.access(key);
+            }
+        }
+
+        assert!(slru.warm.contains("read"));
+        assert_eq!(slru.victim("").as_deref(), Some("a"));
+        assert_eq!(slru.victim("a").as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_reading_a_cold_item_promotes_it() {
+        let mut slru = Slru::default();
+        for key in ["a", "b", "c", "d", "e"] {
+            slru.insert(key);
+        }
+        assert!(slru.cold.contains("a"));
+
+        slru.access("a");
+        assert!(slru.warm.contains("a"));
+        assert_eq!(slru.victim("").as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_remove_from_any_segment() {
+        let mut slru = Slru::default();
+        for key in ["a", "b", "c", "d", "e"] {
+            slru.insert(key);
+        }
+        slru.access("e");
+        for key in ["a", "b", "c", "d", "e"] {
+            slru.remove(key);
+        }
+
+        assert_eq!(slru.len(), 0);
+        assert!(slru.active.is_empty());
+        assert_eq!(slru.victim(""), None);
+    }
+}
@@ -0,0 +1,240 @@
+use super::list::LruList;
+use super::EvictionPolicy;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+/// Largest share of items, in percent, kept in the admission window.
+const WINDOW_PERCENT: usize = 1;
+/// Largest share of the main space, in percent, kept in its protected segment.
+const PROTECTED_PERCENT: usize = 80;
+/// Each sketch row has `1 << SKETCH_WIDTH_BITS` counters.
+const SKETCH_WIDTH_BITS: u32 = 12;
+
+const SKETCH_DEPTH: usize = 4;
+/// Odd multipliers that derive one counter per row from a single hash.
+const SKETCH_SEEDS: [u64; SKETCH_DEPTH] = [
+    0x9e37_79b9_7f4a_7c15,
+    0xc2b2_ae3d_27d4_eb4f,
+    0x1656_67b1_9e37_79f9,
+    0xd6e8_feb8_6659_fd93,
+];
+const SKETCH_MAX_COUNT: u8 = 15;
+
+/// Approximate access counts in fixed memory. All counters are halved once enough accesses have
+/// been recorded, so that past popularity fades.
+pub(crate) struct CountMinSketch {
+    counters: Vec<u8>,
+    width_bits: u32,
+    hasher: RandomState,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl CountMinSketch {
+    pub(crate) fn new(width_bits: u32) -> Self {
+        let width = 1 << width_bits;
+        Self {
+            counters: vec![0; SKETCH_DEPTH * width],
+            width_bits,
+            hasher: RandomState::new(),
+            additions: 0,
+            sample_size: 10 * width,
+        }
+    }
+
+    fn indexes(&self, key: &str) -> [usize; SKETCH_DEPTH] {
+        let hash = self.hasher.hash_one(key);
+        std::array::from_fn(|row| {
+            let column = hash.wrapping_mul(SKETCH_SEEDS[row]) >> (u64::BITS - self.width_bits);
+            (row << self.width_bits) + column as usize
+        })
+    }
+
+    pub(crate) fn increment(&mut self, key: &str) {
+        for index in self.indexes(key) {
+            let counter = &mut self.counters[index];
+            *counter = (*counter + 1).min(SKETCH_MAX_COUNT);
+        }
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            for counter in &mut self.counters {
+                *counter /= 2;
+            }
+            self.additions /= 2;
+        }
+    }
+
+    pub(crate) fn footprint(&self) -> u64 {
+        self.counters.len() as u64
+    }
+
+    pub(crate) fn estimate(&self, key: &str) -> u8 {
+        self.indexes(key)
+            .into_iter()
+            .map(|index| self.counters[index])
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+/// Window TinyLFU.
+///
+/// New items enter a small LRU window and then a segmented main space. When an item has to go,
+/// the newest item in the main space's probation segment competes with its oldest one, and the
+/// one the sketch has seen less often is evicted; a scan of one-off keys therefore cannot push
+/// out items that are used repeatedly.
+pub struct WTinyLfu {
+    window: LruList,
+    probation: LruList,
+    protected: LruList,
+    sketch: CountMinSketch,
+}
+
+impl Default for WTinyLfu {
+    fn default() -> Self {
+        Self {
+            window: LruList::default(),
+            probation: LruList::default(),
+            protected: LruList::default(),
+            sketch: CountMinSketch::new(SKETCH_WIDTH_BITS),
+        }
+    }
+}
+
+impl WTinyLfu {
+    fn len(&self) -> usize {
+        self.window.len() + self.probation.len() + self.protected.len()
+    }
+}
+
+impl EvictionPolicy for WTinyLfu {
+    fn name(&self) -> &'static str {
+        "w-tinylfu"
+    }
+
+    fn insert(&mut self, key: &str) {
+        self.sketch.increment(key);
+        self.window.push(key);
+        let window_limit = (self.len() * WINDOW_PERCENT / 100).max(1);
+        while self.window.len() > window_limit {
+            let Some(key) = self.window.pop_oldest() else {
+                break;
+            };
+            self.probation.push(&key);
+        }
+    }
+
+    fn access(&mut self, key: &str) {
+        if self.window.contains(key) {
+            self.window.push(key);
+        } else if self.probation.remove(key) {
+            self.protected.push(key);
+            let main = self.probation.len() + self.protected.len();
+            let protected_limit = (main * PROTECTED_PERCENT / 100).max(1);
+            while self.protected.len() > protected_limit {
+                let Some(key) = self.protected.pop_oldest() else {
+                    break;
+                };
+                self.probation.push(&key);
+            }
+        } else if self.protected.contains(key) {
+            self.protected.push(key);
+        } else {
+            return;
+        }
+        self.sketch.increment(key);
+    }
+
+    fn remove(&mut self, key: &str) {
+        let _ = self.window.remove(key) || self.probation.remove(key) || self.protected.remove(key);
+    }
+
+    fn victim(&mut self, except: &str) -> Option<String> {
+        let incumbent = self
+            .probation
+            .oldest_except(except)
+            .or_else(|| self.protected.oldest_except(except));
+        let candidate = self
+            .probation
+            .newest_except(except)
+            .filter(|candidate| Some(*candidate) != incumbent)
+            .or_else(|| self.window.oldest_except(except));
+        let loser = match (candidate, incumbent) {
+            (Some(candidate), Some(incumbent)) => {
+                if self.sketch.estimate(candidate) > self.sketch.estimate(incumbent) {
+                    incumbent
+                } else {
+                    candidate
+                }
+            }
+            (candidate, incumbent) => incumbent.or(candidate)?,
+        };
+        Some(loser.to_string())
+    }
+
+    fn key_copies(&self) -> u64 {
+        // A key is in exactly one of the lists.
+        LruList::KEY_COPIES
+    }
+
+    fn footprint(&self) -> u64 {
+        self.sketch.footprint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sketch_estimates() {
+        let mut sketch = CountMinSketch::new(8);
+        for _ in 0..3 {
+            sketch.increment("often");
+        }
+        sketch.increment("once");
+
+        assert!(sketch.estimate("often") >= 3);
+        assert!(sketch.estimate("often") > sketch.estimate("never"));
+    }
+
+    #[test]
+    fn test_sketch_saturates_and_ages() {
+        let mut sketch = CountMinSketch::new(4);
+        for _ in 0..sketch.sample_size - 1 {
+            sketch.increment("key");
+        }
+        assert_eq!(sketch.estimate("key"), SKETCH_MAX_COUNT);
+
+        sketch.increment("key");
+        assert_eq!(sketch.estimate("key"), SKETCH_MAX_COUNT / 2);
+        assert_eq!(sketch.additions, sketch.sample_size / 2);
+    }
+
+    #[test]
+    fn test_frequent_incumbent_beats_new_candidate() {
+        let mut policy = WTinyLfu::default();
+        for key in ["frequent", "new", "window"] {
+            policy.insert(key);
+        }
+        for _ in 0..5 {
+            policy.sketch.increment("frequent");
+        }
+
+        assert_eq!(policy.victim("").as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn test_candidate_with_more_hits_is_admitted() {
+        let mut policy = WTinyLfu::default();
+        for key in ["old", "popular", "window"] {
+            policy.insert(key);
+        }
+        for _ in 0..5 {
+            policy.sketch.increment("popular");
+        }
+
+        assert_eq!(policy.victim("").as_deref(), Some("old"));
+        assert_eq!(policy.victim("old").as_deref(), Some("window"));
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+
+/// Chunk sizes are rounded up to a multiple of this.
+const CHUNK_ALIGN: u64 = 8;
+
+/// Items whose charge fits in one chunk size, and the pages holding those chunks.
+pub(crate) struct SlabClass {
+    pub(crate) chunk_size: u64,
+    pub(crate) chunks_per_page: u64,
+    pub(crate) total_pages: u64,
+    pub(crate) used_chunks: u64,
+    /// Items evicted to make room for another item of this class.
+    pub(crate) evictions: u64,
+    /// `evictions` when the rebalancer last looked.
+    seen_evictions: u64,
+}
+
+impl SlabClass {
+    fn new(chunk_size: u64, page_size: u64) -> Self {
+        Self {
+            chunk_size,
+            chunks_per_page: (page_size / chunk_size).max(1),
+            total_pages: 0,
+            used_chunks: 0,
+            evictions: 0,
+            seen_evictions: 0,
+        }
+    }
+
+    pub(crate) fn free_chunks(&self) -> u64 {
+        self.total_pages * self.chunks_per_page - self.used_chunks
+    }
+
+    fn has_free_page(&self) -> bool {
+        self.total_pages > 0 && self.free_chunks() >= self.chunks_per_page
+    }
+}
+
+/// Memory accounting in the style of memcached's slab allocator.
+///
+/// The memory limit is cut into pages, which are handed to size classes as they fill up. An item
+/// takes one chunk of the smallest class it fits in, so memory use includes the space items leave
+/// unused in their chunks.
+pub(crate) struct Slabs {
+    classes: Vec<SlabClass>,
+    page_size: u64,
+    /// Pages not handed to any class yet.
+    free_pages: u64,
+    /// Memory set aside outside the slabs, and the pages taken for it.
+    reserved: u64,
+    reserved_pages: u64,
+}
+
+impl Slabs {
+    /// Classes start at `min_chunk` and grow by `growth_factor` up to a last class of one chunk
+    /// per page.
+    pub(crate) fn new(max_bytes: u64, page_size: u64, min_chunk: u64, growth_factor: f64) -> Self {
+        let page_size = page_size.max(1);
+        let mut classes = vec![];
+        let mut size = min_chunk.max(1);
+        while (size as f64) <= page_size as f64 / growth_factor {
+            size = size.next_multiple_of(CHUNK_ALIGN);
+            classes.push(SlabClass::new(size, page_size));
+            size = ((size as f64 * growth_factor) as u64).max(size + 1);
+        }
+        classes.push(SlabClass::new(page_size, page_size));
+        Self {
+            classes,
+            page_size,
+            free_pages: max_bytes / page_size,
+            reserved: 0,
+            reserved_pages: 0,
+        }
+    }
+
+    pub(crate) fn classes(&self) -> &[SlabClass] {
+        &self.classes
+    }
+
+    /// The class storing an item of `charge` bytes, if it fits in a page.
+    pub(crate) fn class_for(&self, charge: u64) -> Option<usize> {
+        let class = self
+            .classes
+            .partition_point(|class| class.chunk_size < charge);
+        (class < self.classes.len()).then_some(class)
+    }
+
+    /// Takes a chunk of `class`, giving the class another page if it is full. Returns false when
+    /// no page is left.
+    pub(crate) fn allocate(&mut self, class: usize) -> bool {
+        let slab_class = &mut self.classes[class];
+        if slab_class.free_chunks() == 0 {
+            if self.free_pages == 0 {
+                return false;
+            }
+            self.free_pages -= 1;
+            slab_class.total_pages += 1;
+        }
+        slab_class.used_chunks += 1;
+        true
+    }
+
+    /// Sets aside `bytes` of the limit for state kept outside the slabs, taking whole pages while
+    /// any are unassigned.
+    pub(crate) fn reserve(&mut self, bytes: u64) {
+        self.reserved += bytes;
+        while self.reserved_pages * self.page_size < self.reserved && self.free_pages > 0 {
+            self.free_pages -= 1;
+            self.reserved_pages += 1;
+        }
+    }
+
+    pub(crate) fn release(&mut self, class: usize) {
+        self.classes[class].used_chunks -= 1;
+    }
+
+    pub(crate) fn record_eviction(&mut self, class: usize) {
+        self.classes[class].evictions += 1;
+    }
+
+    /// Picks a page to move to the class that evicted the most since the last call, from a class
+    /// that evicted nothing and either has a page of free chunks or more than one page. Nothing
+    /// moves while pages are left unassigned.
+    pub(crate) fn plan_move(&mut self) -> Option<(usize, usize)> {
+        let recent: Vec<u64> = self
+            .classes
+            .iter_mut()
+            .map(|class| {
+                let recent = class.evictions - class.seen_evictions;
+                class.seen_evictions = class.evictions;
+                recent
+            })
+            .collect();
+        if self.free_pages > 0 {
+            return None;
+        }
+        let destination = (0..self.classes.len())
+            .filter(|&class| recent[class] > 0)
+            .max_by_key(|&class| recent[class])?;
+        let source = (0..self.classes.len())
+            .filter(|&class| class != destination && recent[class] == 0)
+            .filter(|&class| {
+                self.classes[class].has_free_page() || self.classes[class].total_pages > 1
+            })
+            .max_by_key(|&class| {
+                (
+                    self.classes[class].has_free_page(),
+                    self.classes[class].total_pages,
+                )
+            })?;
+        Some((source, destination))
+    }
+
+    /// Chunks of `class` that still have to be freed before it can give up a page.
+    pub(crate) fn missing_for_page(&self, class: usize) -> u64 {
+        let class = &self.classes[class];
+        class.chunks_per_page.saturating_sub(class.free_chunks())
+    }
+
+    /// Moves a page from `source` to `destination` if `source` has a page worth of free chunks.
+    pub(crate) fn move_page(&mut self, source: usize, destination: usize) -> bool {
+        if !self.classes[source].has_free_page() {
+            return false;
+        }
+        self.classes[source].total_pages -= 1;
+        self.classes[destination].total_pages += 1;
+        true
+    }
+
+    /// Table for `stats slabs`, given the bytes requested by the live items of each class.
+    /// Classes are numbered from 1 and only listed once they have a page.
+    pub(crate) fn report(&self, mem_requested: &BTreeMap<usize, u64>) -> Vec<(String, String)> {
+        let mut stats = vec![];
+        let mut active_slabs = 0;
+        let mut total_pages = 0;
+        for (class, slab_class) in self.classes.iter().enumerate() {
+            if slab_class.total_pages == 0 {
+                continue;
+            }
+            active_slabs += 1;
+            total_pages += slab_class.total_pages;
+            let id = class + 1;
+            stats.extend([
+                (format!("{id}:chunk_size"), slab_class.chunk_size),
+                (format!("{id}:chunks_per_page"), slab_class.chunks_per_page),
+                (format!("{id}:total_pages"), slab_class.total_pages),
+                (
+                    format!("{id}:total_chunks"),
+                    slab_class.total_pages * slab_class.chunks_per_page,
+                ),
+                (format!("{id}:used_chunks"), slab_class.used_chunks),
+                (format!("{id}:free_chunks"), slab_class.free_chunks()),
+                (
+                    format!("{id}:mem_requested"),
+                    mem_requested.get(&class).copied().unwrap_or(0),
+                ),
+            ]);
+        }
+        stats.push(("active_slabs".to_string(), active_slabs));
+        stats.push(("total_malloced".to_string(), total_pages * self.page_size));
+        stats
+            .into_iter()
+            .map(|(name, value)| (name, value.to_string()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classes_grow_by_factor() {
+        let slabs = Slabs::new(1024, 1024, 96, 1.25);
+        let sizes: Vec<u64> = slabs.classes().iter().map(|c| c.chunk_size).collect();
+
+        assert_eq!(
+            sizes,
+            vec![96, 120, 152, 192, 240, 304, 384, 480, 600, 752, 1024]
+        );
+        assert_eq!(slabs.classes()[0].chunks_per_page, 10);
+        assert_eq!(slabs.class_for(1), Some(0));
+        assert_eq!(slabs.class_for(96), Some(0));
+        assert_eq!(slabs.class_for(97), Some(1));
+        assert_eq!(slabs.class_for(1024), Some(10));
+        assert_eq!(slabs.class_for(1025), None);
+    }
+
+    #[test]
+    fn test_pages_are_assigned_on_demand() {
+        let mut slabs = Slabs::new(256, 128, 64, 2.0);
+
+        assert!(slabs.allocate(0));
+        assert!(slabs.allocate(0));
+        assert!(slabs.allocate(1));
+        assert_eq!(slabs.classes()[0].total_pages, 1);
+        assert!(!slabs.allocate(0));
+        assert!(!slabs.allocate(1));
+
+        slabs.release(0);
+        assert!(slabs.allocate(0));
+        assert_eq!(slabs.classes()[0].free_chunks(), 0);
+    }
+
+    #[test]
+    fn test_reserve_takes_whole_pages() {
+        let mut slabs = Slabs::new(384, 128, 64, 2.0);
+        slabs.reserve(100);
+        slabs.reserve(28);
+        assert_eq!(slabs.free_pages, 2);
+        slabs.reserve(1);
+        assert_eq!(slabs.free_pages, 1);
+
+        assert!(slabs.allocate(1));
+        assert!(!slabs.allocate(0));
+    }
+
+    #[test]
+    fn test_page_moves_to_evicting_class() {
+        let mut slabs = Slabs::new(256, 128, 64, 2.0);
+        assert!(slabs.allocate(0));
+        assert!(slabs.allocate(1));
+        slabs.release(0);
+        slabs.record_eviction(1);
+
+        assert_eq!(slabs.plan_move(), Some((0, 1)));
+        assert!(slabs.move_page(0, 1));
+        assert_eq!(slabs.classes()[0].total_pages, 0);
+        assert_eq!(slabs.classes()[1].total_pages, 2);
+        assert!(slabs.allocate(1));
+
+        // Evictions are only counted once.
+        assert_eq!(slabs.plan_move(), None);
+    }
+
+    #[test]
+    fn test_busy_class_needs_chunks_freed_before_moving() {
+        let mut slabs = Slabs::new(384, 128, 64, 2.0);
+        for _ in 0..4 {
+            assert!(slabs.allocate(0));
+        }
+        assert!(slabs.allocate(1));
+        slabs.record_eviction(1);
+
+        assert_eq!(slabs.plan_move(), Some((0, 1)));
+        assert_eq!(slabs.missing_for_page(0), 2);
+        assert!(!slabs.move_page(0, 1));
+        slabs.release(0);
+        slabs.release(0);
+        assert_eq!(slabs.missing_for_page(0), 0);
+        assert!(slabs.move_page(0, 1));
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+macro_rules! storage_statistics {
+    (counters: [$($counter:ident),* $(,)?], gauges: [$($gauge:ident),* $(,)?]) => {
+        /// Item and command counters reported by `stats`.
+        #[derive(Default)]
+        pub(crate) struct StorageStatistics {
+            $(pub(crate) $counter: AtomicU64,)*
+            $(pub(crate) $gauge: AtomicU64,)*
+        }
+
+        impl StorageStatistics {
+            pub(crate) fn report(&self) -> Vec<(String, String)> {
+                vec![
+                    $((
+                        stringify!($counter).to_string(),
+                        self.$counter.load(Ordering::Relaxed).to_string(),
+                    ),)*
+                    $((
+                        stringify!($gauge).to_string(),
+                        self.$gauge.load(Ordering::Relaxed).to_string(),
+                    ),)*
+                ]
+            }
+
+            /// Zeroes the counters; gauges describe current contents and are kept.
+            pub(crate) fn reset(&self) {
+                $(self.$counter.store(0, Ordering::Relaxed);)*
+            }
+        }
+    };
+}
+
+storage_statistics!(
+    counters: [
+        cmd_get,
+        cmd_set,
+        cmd_flush,
+        cmd_touch,
+        get_hits,
+        get_misses,
+        delete_misses,
+        delete_hits,
+        incr_misses,
+        incr_hits,
+        decr_misses,
+        decr_hits,
+        cas_misses,
+        cas_hits,
+        cas_badval,
+        touch_hits,
+        touch_misses,
+        total_items,
+        evictions,
+        expired_unfetched,
+        reclaimed,
+        slabs_moved,
+        slab_reassign_evictions_nomem,
+    ],
+    gauges: [limit_maxbytes]
+);
+
+pub(crate) fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn hit_or_miss(hit: bool, hits: &AtomicU64, misses: &AtomicU64) {
+    bump(if hit { hits } else { misses });
+}
+
+impl StorageStatistics {
+    /// Share of `get` keys that were found, or 0 before the first lookup.
+    pub(crate) fn hit_ratio(&self) -> f64 {
+        let hits = self.get_hits.load(Ordering::Relaxed);
+        let lookups = hits + self.get_misses.load(Ordering::Relaxed);
+        if lookups == 0 {
+            return 0.0;
+        }
+        hits as f64 / lookups as f64
+    }
+}
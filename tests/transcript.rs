@@ -0,0 +1,655 @@
+use endpoint::{serve, ServerOptions};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use storage::HashMapStorage;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn connect() -> TcpStream {
+    connect_with(ServerOptions::default()).await
+}
+
+async fn connect_with(options: ServerOptions) -> TcpStream {
+    let address = listen(options).await;
+    TcpStream::connect(address).await.expect("Can connect")
+}
+
+async fn listen(options: ServerOptions) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Can bind");
+    let address = listener.local_addr().expect("Has local address");
+    tokio::spawn(serve(
+        listener,
+        Arc::new(HashMapStorage::default()),
+        options,
+    ));
+    address
+}
+
+async fn transcript(stream: &mut TcpStream, request: &[u8], expected: &[u8]) {
+    stream.write_all(request).await.expect("Can write");
+    let mut response = vec![0; expected.len()];
+    stream
+        .read_exact(&mut response)
+        .await
+        .expect("Can read response");
+    assert_eq!(
+        String::from_utf8_lossy(&response),
+        String::from_utf8_lossy(expected)
+    );
+}
+
+#[tokio::test]
+async fn test_storage_commands() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"set key 5 0 5\r\nvalue\r\n", b"STORED\r\n").await;
+    transcript(&mut stream, b"add key 0 0 1\r\nx\r\n", b"NOT_STORED\r\n").await;
+    transcript(
+        &mut stream,
+        b"replace nokey 0 0 1\r\nx\r\n",
+        b"NOT_STORED\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"append key 0 0 1\r\n!\r\n", b"STORED\r\n").await;
+    transcript(&mut stream, b"prepend key 0 0 1\r\n>\r\n", b"STORED\r\n").await;
+    transcript(
+        &mut stream,
+        b"get key\r\n",
+        b"VALUE key 5 7\r\n>value!\r\nEND\r\n",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_get_multi() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"set key1 1 0 2\r\nv1\r\n", b"STORED\r\n").await;
+    transcript(&mut stream, b"set key3 3 0 4\r\nv333\r\n", b"STORED\r\n").await;
+    transcript(
+        &mut stream,
+        b"get key1 key2 key3\r\n",
+        b"VALUE key1 1 2\r\nv1\r\nVALUE key3 3 4\r\nv333\r\nEND\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"get key2\r\n", b"END\r\n").await;
+}
+
+#[tokio::test]
+async fn test_gets_and_cas() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"set key 0 0 1\r\na\r\n", b"STORED\r\n").await;
+    transcript(
+        &mut stream,
+        b"gets key\r\n",
+        b"VALUE key 0 1 1\r\na\r\nEND\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"cas key 0 0 1 1\r\nb\r\n", b"STORED\r\n").await;
+    transcript(&mut stream, b"cas key 0 0 1 1\r\nc\r\n", b"EXISTS\r\n").await;
+    transcript(&mut stream, b"cas nokey 0 0 1 1\r\nc\r\n", b"NOT_FOUND\r\n").await;
+}
+
+#[tokio::test]
+async fn test_delete_and_arithmetic() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"delete key\r\n", b"NOT_FOUND\r\n").await;
+    transcript(&mut stream, b"incr key 1\r\n", b"NOT_FOUND\r\n").await;
+    transcript(&mut stream, b"set key 0 0 1\r\n1\r\n", b"STORED\r\n").await;
+    transcript(&mut stream, b"incr key 41\r\n", b"42\r\n").await;
+    transcript(&mut stream, b"decr key 50\r\n", b"0\r\n").await;
+    transcript(&mut stream, b"delete key\r\n", b"DELETED\r\n").await;
+    transcript(&mut stream, b"set key 0 0 1\r\na\r\n", b"STORED\r\n").await;
+    transcript(
+        &mut stream,
+        b"incr key 1\r\n",
+        b"CLIENT_ERROR cannot increment or decrement non-numeric value\r\n",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_noreply() {
+    let mut stream = connect().await;
+
+    stream
+        .write_all(b"set key 0 0 1 noreply\r\na\r\ndelete nokey noreply\r\n")
+        .await
+        .expect("Can write");
+    transcript(
+        &mut stream,
+        b"get key\r\n",
+        b"VALUE key 0 1\r\na\r\nEND\r\n",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_commands_without_arguments() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"version\r\n", b"VERSION 0.1.0\r\n").await;
+    transcript(&mut stream, b"unknown\r\n", b"ERROR\r\n").await;
+}
+
+#[tokio::test]
+async fn test_bad_data_chunk_keeps_connection_in_sync() {
+    let mut stream = connect().await;
+
+    transcript(
+        &mut stream,
+        b"set key 0 0 1\r\nvalue\r\n",
+        b"CLIENT_ERROR bad data chunk\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"set key 0 0 5\r\nvalue\r\n", b"STORED\r\n").await;
+    transcript(
+        &mut stream,
+        b"get key\r\n",
+        b"VALUE key 0 5\r\nvalue\r\nEND\r\n",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_limits_keep_connection_serving() {
+    let mut stream = connect_with(ServerOptions {
+        max_line_length: 32,
+        max_item_size: 4,
+        ..ServerOptions::default()
+    })
+    .await;
+
+    transcript(
+        &mut stream,
+        b"set key 0 0 10\r\n0123456789\r\n",
+        b"SERVER_ERROR object too large for cache\r\n",
+    )
+    .await;
+    transcript(
+        &mut stream,
+        b"get aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n",
+        b"CLIENT_ERROR line too long\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"set key 0 0 4\r\nabcd\r\n", b"STORED\r\n").await;
+    transcript(
+        &mut stream,
+        b"get key\r\n",
+        b"VALUE key 0 4\r\nabcd\r\nEND\r\n",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_connection_survives_malformed_input() {
+    let mut stream = connect().await;
+
+    transcript(
+        &mut stream,
+        b"set key x 0 5\r\nvalue\r\n",
+        b"CLIENT_ERROR bad command line format\r\n",
+    )
+    .await;
+    transcript(
+        &mut stream,
+        b"incr key abc\r\n",
+        b"CLIENT_ERROR invalid numeric delta argument\r\n",
+    )
+    .await;
+    transcript(
+        &mut stream,
+        b"\xff\xfe\x00\r\n",
+        b"CLIENT_ERROR bad command line format\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"\r\n", b"ERROR\r\n").await;
+    transcript(&mut stream, b"set key 0 0\r\n", b"ERROR\r\n").await;
+    transcript(&mut stream, b"set key 0 0 5\r\nvalue\r\n", b"STORED\r\n").await;
+    transcript(
+        &mut stream,
+        b"get key\r\n",
+        b"VALUE key 0 5\r\nvalue\r\nEND\r\n",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_malformed_input_split_across_packets() {
+    let mut stream = connect().await;
+
+    stream
+        .write_all(b"set key 0 0 3\r\nab")
+        .await
+        .expect("Can write");
+    transcript(&mut stream, b"cdef\r\n", b"CLIENT_ERROR bad data chunk\r\n").await;
+    transcript(&mut stream, b"get key\r\n", b"END\r\n").await;
+}
+
+#[tokio::test]
+async fn test_touch_and_gat() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"touch key 10\r\n", b"NOT_FOUND\r\n").await;
+    transcript(&mut stream, b"set key 0 0 1\r\na\r\n", b"STORED\r\n").await;
+    transcript(&mut stream, b"touch key 10\r\n", b"TOUCHED\r\n").await;
+    transcript(
+        &mut stream,
+        b"gat 100 key nokey\r\n",
+        b"VALUE key 0 1\r\na\r\nEND\r\n",
+    )
+    .await;
+    transcript(
+        &mut stream,
+        b"gats 100 key\r\n",
+        b"VALUE key 0 1 1\r\na\r\nEND\r\n",
+    )
+    .await;
+    transcript(
+        &mut stream,
+        b"gat -1 key\r\n",
+        b"VALUE key 0 1\r\na\r\nEND\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"get key\r\n", b"END\r\n").await;
+}
+
+#[tokio::test]
+async fn test_flush_all() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"set key 0 0 1\r\na\r\n", b"STORED\r\n").await;
+    transcript(&mut stream, b"flush_all 100\r\n", b"OK\r\n").await;
+    transcript(
+        &mut stream,
+        b"get key\r\n",
+        b"VALUE key 0 1\r\na\r\nEND\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"flush_all\r\n", b"OK\r\n").await;
+    transcript(&mut stream, b"get key\r\n", b"END\r\n").await;
+    stream
+        .write_all(b"set key 0 0 1\r\nb\r\nflush_all noreply\r\n")
+        .await
+        .expect("Can write");
+    transcript(&mut stream, b"get key\r\n", b"STORED\r\nEND\r\n").await;
+}
+
+async fn read_stats(stream: &mut TcpStream) -> Vec<(String, String)> {
+    let mut response = Vec::new();
+    while !response.ends_with(b"END\r\n") {
+        let mut chunk = [0; 1024];
+        let n = stream.read(&mut chunk).await.expect("Can read response");
+        assert_ne!(n, 0, "Connection closed before END");
+        response.extend_from_slice(&chunk[..n]);
+    }
+    String::from_utf8(response)
+        .expect("Stats are text")
+        .lines()
+        .filter_map(|line| {
+            let mut tokens = line.strip_prefix("STAT ")?.splitn(2, ' ');
+            Some((tokens.next()?.to_string(), tokens.next()?.to_string()))
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"set key 0 0 5\r\nvalue\r\n", b"STORED\r\n").await;
+    transcript(
+        &mut stream,
+        b"get key\r\n",
+        b"VALUE key 0 5\r\nvalue\r\nEND\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"get nokey\r\n", b"END\r\n").await;
+
+    stream.write_all(b"stats\r\n").await.expect("Can write");
+    let stats = read_stats(&mut stream).await;
+    let stat = |name: &str| {
+        stats
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_else(|| panic!("Missing {name}"))
+    };
+
+    for name in [
+        "pid",
+        "uptime",
+        "time",
+        "version",
+        "bytes_read",
+        "bytes_written",
+        "limit_maxbytes",
+        "threads",
+        "evictions",
+    ] {
+        stat(name);
+    }
+    assert_eq!(stat("curr_connections"), "1");
+    assert_eq!(stat("total_connections"), "1");
+    assert_eq!(stat("cmd_get"), "2");
+    assert_eq!(stat("cmd_set"), "1");
+    assert_eq!(stat("get_hits"), "1");
+    assert_eq!(stat("get_misses"), "1");
+    assert_eq!(stat("curr_items"), "1");
+    assert_eq!(stat("total_items"), "1");
+    assert_eq!(stat("bytes"), "8");
+}
+
+#[tokio::test]
+async fn test_stats_subcommands() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"set key 0 0 5\r\nvalue\r\n", b"STORED\r\n").await;
+
+    stream
+        .write_all(b"stats settings\r\n")
+        .await
+        .expect("Can write");
+    let settings = read_stats(&mut stream).await;
+    assert!(settings.contains(&("item_size_max".to_string(), "1048576".to_string())));
+    assert!(settings.iter().any(|(key, _)| key == "maxbytes"));
+
+    stream
+        .write_all(b"stats items\r\n")
+        .await
+        .expect("Can write");
+    let items = read_stats(&mut stream).await;
+    assert!(items.contains(&("items:1:number".to_string(), "1".to_string())));
+
+    transcript(&mut stream, b"stats reset\r\n", b"RESET\r\n").await;
+    stream.write_all(b"stats\r\n").await.expect("Can write");
+    let stats = read_stats(&mut stream).await;
+    assert!(stats.contains(&("cmd_set".to_string(), "0".to_string())));
+    assert!(stats.contains(&("curr_items".to_string(), "1".to_string())));
+
+    transcript(&mut stream, b"stats bogus\r\n", b"ERROR\r\n").await;
+}
+
+async fn assert_closed(stream: &mut TcpStream) {
+    let mut buf = [0; 1];
+    assert_eq!(stream.read(&mut buf).await.expect("Can read"), 0);
+}
+
+#[tokio::test]
+async fn test_quit_and_verbosity() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"verbosity 1\r\n", b"OK\r\n").await;
+    transcript(
+        &mut stream,
+        b"verbosity 1 noreply\r\nversion\r\n",
+        format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")).as_bytes(),
+    )
+    .await;
+    transcript(&mut stream, b"shutdown\r\n", b"ERROR\r\n").await;
+    stream.write_all(b"quit\r\n").await.expect("Can write");
+    assert_closed(&mut stream).await;
+}
+
+#[tokio::test]
+async fn test_shutdown() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Can bind");
+    let address: SocketAddr = listener.local_addr().expect("Has local address");
+    let server = tokio::spawn(serve(
+        listener,
+        Arc::new(HashMapStorage::default()),
+        ServerOptions {
+            enable_shutdown: true,
+            ..ServerOptions::default()
+        },
+    ));
+    let mut idle = TcpStream::connect(address).await.expect("Can connect");
+    let mut stream = TcpStream::connect(address).await.expect("Can connect");
+
+    transcript(&mut stream, b"shutdown\r\n", b"OK\r\n").await;
+    assert_closed(&mut stream).await;
+    assert_closed(&mut idle).await;
+    server
+        .await
+        .expect("Server task completes")
+        .expect("Server stops cleanly");
+}
+
+#[tokio::test]
+async fn test_meta_commands() {
+    let mut stream = connect().await;
+
+    transcript(&mut stream, b"ms key 5 F3 T0\r\nvalue\r\n", b"HD\r\n").await;
+    transcript(
+        &mut stream,
+        b"mg key v f t k Oabc\r\n",
+        b"VA 5 f3 t-1 kkey Oabc\r\nvalue\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"mg nokey v\r\n", b"EN\r\n").await;
+    transcript(
+        &mut stream,
+        b"mg nokey v q\r\nmg key s q\r\nmn\r\n",
+        b"HD s5\r\nMN\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"ms key 1 ME\r\nx\r\n", b"NS\r\n").await;
+    transcript(
+        &mut stream,
+        b"me key\r\n",
+        b"ME key exp=-1 la=0 cas=1 size=5\r\n",
+    )
+    .await;
+
+    transcript(&mut stream, b"md key I\r\n", b"HD\r\n").await;
+    transcript(&mut stream, b"mg key c\r\n", b"HD c2 W X\r\n").await;
+    transcript(&mut stream, b"mg key\r\n", b"HD X Z\r\n").await;
+    transcript(&mut stream, b"md key q\r\nmd key\r\n", b"NF\r\n").await;
+
+    transcript(&mut stream, b"ma counter\r\n", b"NF\r\n").await;
+    transcript(&mut stream, b"ma counter N0 J5 v\r\n", b"VA 1\r\n5\r\n").await;
+    transcript(&mut stream, b"ma counter MD D2 v\r\n", b"VA 1\r\n3\r\n").await;
+    transcript(
+        &mut stream,
+        b"mg key X\r\n",
+        b"CLIENT_ERROR invalid flag\r\n",
+    )
+    .await;
+}
+
+struct BinaryPacket {
+    opcode: u8,
+    status: u16,
+    opaque: u32,
+    cas: u64,
+    extras: Vec<u8>,
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+async fn send_binary(
+    stream: &mut TcpStream,
+    opcode: u8,
+    extras: &[u8],
+    key: &[u8],
+    value: &[u8],
+    cas: u64,
+) {
+    let mut packet = vec![0x80, opcode];
+    packet.extend_from_slice(&(key.len() as u16).to_be_bytes());
+    packet.push(extras.len() as u8);
+    packet.extend_from_slice(&[0, 0, 0]);
+    packet.extend_from_slice(&((extras.len() + key.len() + value.len()) as u32).to_be_bytes());
+    packet.extend_from_slice(&u32::from(opcode).to_be_bytes());
+    packet.extend_from_slice(&cas.to_be_bytes());
+    packet.extend_from_slice(extras);
+    packet.extend_from_slice(key);
+    packet.extend_from_slice(value);
+    stream.write_all(&packet).await.expect("Can write");
+}
+
+async fn read_binary(stream: &mut TcpStream) -> BinaryPacket {
+    let mut header = [0; 24];
+    stream
+        .read_exact(&mut header)
+        .await
+        .expect("Can read header");
+    assert_eq!(header[0], 0x81);
+    let key_length = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let extras_length = header[4] as usize;
+    let body_length = u32::from_be_bytes(header[8..12].try_into().unwrap()) as usize;
+    let mut body = vec![0; body_length];
+    stream.read_exact(&mut body).await.expect("Can read body");
+    let value = body.split_off(extras_length + key_length);
+    let key = body.split_off(extras_length);
+    BinaryPacket {
+        opcode: header[1],
+        status: u16::from_be_bytes([header[6], header[7]]),
+        opaque: u32::from_be_bytes(header[12..16].try_into().unwrap()),
+        cas: u64::from_be_bytes(header[16..24].try_into().unwrap()),
+        extras: body,
+        key,
+        value,
+    }
+}
+
+#[tokio::test]
+async fn test_binary_protocol() {
+    let mut stream = connect().await;
+
+    send_binary(
+        &mut stream,
+        0x01,
+        &[0, 0, 0, 5, 0, 0, 0, 0],
+        b"key",
+        b"value",
+        0,
+    )
+    .await;
+    let set = read_binary(&mut stream).await;
+    assert_eq!((set.opcode, set.status, set.opaque), (0x01, 0, 0x01));
+    assert_ne!(set.cas, 0);
+
+    send_binary(&mut stream, 0x0c, &[], b"key", b"", 0).await;
+    let getk = read_binary(&mut stream).await;
+    assert_eq!(getk.status, 0);
+    assert_eq!(getk.extras, [0, 0, 0, 5]);
+    assert_eq!(getk.key, b"key");
+    assert_eq!(getk.value, b"value");
+    assert_eq!(getk.cas, set.cas);
+
+    send_binary(&mut stream, 0x09, &[], b"nokey", b"", 0).await;
+    send_binary(&mut stream, 0x0a, &[], b"", b"", 0).await;
+    let noop = read_binary(&mut stream).await;
+    assert_eq!((noop.opcode, noop.status), (0x0a, 0));
+
+    send_binary(&mut stream, 0x04, &[], b"key", b"", set.cas + 1).await;
+    let delete = read_binary(&mut stream).await;
+    assert_eq!((delete.opcode, delete.status), (0x04, 0x02));
+
+    let mut incr = Vec::new();
+    incr.extend_from_slice(&2u64.to_be_bytes());
+    incr.extend_from_slice(&10u64.to_be_bytes());
+    incr.extend_from_slice(&0u32.to_be_bytes());
+    send_binary(&mut stream, 0x05, &incr, b"counter", b"", 0).await;
+    assert_eq!(read_binary(&mut stream).await.value, 10u64.to_be_bytes());
+    send_binary(&mut stream, 0x05, &incr, b"counter", b"", 0).await;
+    assert_eq!(read_binary(&mut stream).await.value, 12u64.to_be_bytes());
+
+    send_binary(&mut stream, 0x0b, &[], b"", b"", 0).await;
+    let version = read_binary(&mut stream).await;
+    assert_eq!(version.value, env!("CARGO_PKG_VERSION").as_bytes());
+
+    send_binary(&mut stream, 0x10, &[], b"", b"", 0).await;
+    let mut names = Vec::new();
+    loop {
+        let stat = read_binary(&mut stream).await;
+        if stat.key.is_empty() {
+            break;
+        }
+        names.push(String::from_utf8(stat.key).expect("Stat name is text"));
+    }
+    assert!(names.iter().any(|name| name == "curr_items"));
+
+    send_binary(&mut stream, 0x42, &[], b"", b"", 0).await;
+    assert_eq!(read_binary(&mut stream).await.status, 0x81);
+
+    send_binary(&mut stream, 0x07, &[], b"", b"", 0).await;
+    assert_eq!(read_binary(&mut stream).await.opcode, 0x07);
+    assert_closed(&mut stream).await;
+}
+
+#[tokio::test]
+async fn test_binary_get_leaves_recache_win() {
+    let address = listen(ServerOptions::default()).await;
+    let mut text = TcpStream::connect(address).await.expect("Can connect");
+    let mut binary = TcpStream::connect(address).await.expect("Can connect");
+
+    transcript(&mut text, b"ms key 5\r\nvalue\r\n", b"HD\r\n").await;
+    transcript(&mut text, b"md key I\r\n", b"HD\r\n").await;
+
+    // GET, GETK, GAT and GATK.
+    for (opcode, extras) in [
+        (0x00, &[][..]),
+        (0x0c, &[][..]),
+        (0x1d, &[0, 0, 0, 0][..]),
+        (0x23, &[0, 0, 0, 0][..]),
+    ] {
+        send_binary(&mut binary, opcode, extras, b"key", b"", 0).await;
+        let get = read_binary(&mut binary).await;
+        assert_eq!(
+            (get.opcode, get.status, get.value.as_slice()),
+            (opcode, 0, &b"value"[..])
+        );
+    }
+
+    transcript(&mut text, b"mg key\r\n", b"HD W X\r\n").await;
+}
+
+#[tokio::test]
+async fn test_invalid_keys() {
+    let mut stream = connect().await;
+    let long_key = "k".repeat(251);
+
+    transcript(
+        &mut stream,
+        format!("set {long_key} 0 0 5\r\nvalue\r\n").as_bytes(),
+        b"CLIENT_ERROR bad command line format\r\n",
+    )
+    .await;
+    transcript(
+        &mut stream,
+        b"get k\x01y\r\n",
+        b"CLIENT_ERROR bad command line format\r\n",
+    )
+    .await;
+    transcript(&mut stream, b"set key 0 0 5\r\nvalue\r\n", b"STORED\r\n").await;
+}
+
+#[tokio::test]
+async fn test_pipelined_requests() {
+    for batch_replies in [true, false] {
+        let mut stream = connect_with(ServerOptions {
+            batch_replies,
+            ..ServerOptions::default()
+        })
+        .await;
+        pipelined_requests(&mut stream).await;
+    }
+}
+
+async fn pipelined_requests(stream: &mut TcpStream) {
+    let mut request = b"set key 0 0 5\r\nvalue\r\n".to_vec();
+    let mut expected = b"STORED\r\n".to_vec();
+    for _ in 0..100 {
+        request.extend_from_slice(b"get key nokey\r\n");
+        expected.extend_from_slice(b"VALUE key 0 5\r\nvalue\r\nEND\r\n");
+    }
+    request.extend_from_slice(b"bogus\r\ndelete key noreply\r\nget key\r\nquit\r\n");
+    expected.extend_from_slice(b"ERROR\r\nEND\r\n");
+
+    transcript(stream, &request, &expected).await;
+    assert_closed(stream).await;
+}